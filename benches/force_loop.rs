@@ -0,0 +1,47 @@
+// Compares the scalar and the SIMD pair force loops of every kernel, run with `cargo bench --bench force_loop`. 
+// That both give the same results is checked by `tests/simd.rs`
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use particle_life::{ParticleSettings, ParticleWrapping, Kernel};
+
+const TICKS: u32 = 20;
+
+fn run(particle_count: usize, kernel: Kernel, simd: bool) -> f32 {
+    // Same density as the default world
+    let world_settings = common::world_settings(5000.0 * (particle_count as f32 / 10000.0).sqrt(), particle_count, "force_loop", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings { kernel, simd, ..Default::default() };
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    let mut force_time = 0.0;
+
+    for _ in 0..TICKS {
+        world.update_partitions();
+        world.update_particles(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+
+        force_time += world.velocity_update_time;
+    }
+
+    force_time / TICKS as f32
+}
+
+fn main() {
+    // Measures the loops themselves, not how well they scale with the cores
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    println!("{:>14} {:>10} {:>14} {:>14} {:>8}", "kernel", "particles", "scalar [ms]", "simd [ms]", "speedup");
+
+    pool.install(|| {
+        for kernel in Kernel::all() {
+            for particle_count in [10_000, 30_000, 100_000] {
+                let scalar_time = run(particle_count, kernel, false);
+                let simd_time = run(particle_count, kernel, true);
+
+                println!("{:>14} {:>10} {:>14.3} {:>14.3} {:>7.2}x", kernel.name(), particle_count, scalar_time, simd_time, scalar_time / simd_time);
+            }
+        }
+    });
+}
@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use particle_life::{World, WorldSettings, ParticleSettings, ColorTable, saver};
+
+const USAGE: &str = "\
+Usage: particle_life_headless [OPTIONS]
+
+Options:
+  --world <NAME>           World settings preset (relative to saved/)
+  --particles <NAME>       Particle settings preset (relative to saved/)
+  --colors <NAME>          Color table preset (relative to saved/)
+  --scene <NAME>           Scene with obstacles (relative to saved/)
+  --image <NAME>           Spawn the particles from a PNG image instead (relative to saved/)
+  --ticks <N>              Number of ticks to simulate [default: 1000]
+  --time-step <SECONDS>    Fixed time step of a single tick [default: the world preset's time step]
+  --snapshot-every <N>     Write a snapshot every N ticks, 0 disables snapshots [default: 100]
+  --output <DIR>           Directory for snapshots and stats [default: headless_output]
+  --threads <N>            Number of simulation threads, 0 uses all cores [default: 0]
+  --expect-hash <HASH>     Exit with an error if the final state hash differs, e.g. for golden runs
+  --help                   Print this message";
+
+struct Args {
+    world: Option<String>,
+    particles: Option<String>,
+    colors: Option<String>,
+    scene: Option<String>,
+    image: Option<String>,
+    ticks: u64,
+    time_step: Option<f32>,
+    snapshot_every: u64,
+    output: PathBuf,
+    threads: usize,
+    expect_hash: Option<u64>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self {
+            world: None,
+            particles: None,
+            colors: None,
+            scene: None,
+            image: None,
+            ticks: 1000,
+            time_step: None,
+            snapshot_every: 100,
+            output: PathBuf::from("headless_output"),
+            threads: 0,
+            expect_hash: None,
+        };
+
+        let mut iter = std::env::args().skip(1);
+
+        while let Some(arg) = iter.next() {
+            if arg == "--help" || arg == "-h" {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+
+            let value = iter.next().ok_or_else(|| format!("Missing value for '{}'", arg))?;
+
+            match arg.as_str() {
+                "--world" => args.world = Some(value),
+                "--particles" => args.particles = Some(value),
+                "--colors" => args.colors = Some(value),
+                "--scene" => args.scene = Some(value),
+                "--image" => args.image = Some(value),
+                "--ticks" => args.ticks = parse_value(&arg, &value)?,
+                "--time-step" => args.time_step = Some(parse_value(&arg, &value)?),
+                "--snapshot-every" => args.snapshot_every = parse_value(&arg, &value)?,
+                "--output" => args.output = PathBuf::from(value),
+                "--threads" => args.threads = parse_value(&arg, &value)?,
+                "--expect-hash" => args.expect_hash = Some(
+                    u64::from_str_radix(&value, 16).map_err(|_| format!("Invalid value '{}' for '{}'", value, arg))?
+                ),
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for '{}'", value, arg))
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    tick: u64,
+    time: f32,
+    positions: Vec<[f32; 2]>,
+    velocities: Vec<[f32; 2]>,
+    color_ids: &'a [u8],
+    // The particles are reordered between the snapshots, the ids match them up
+    ids: &'a [usize],
+}
+
+fn write_snapshot(world: &World, tick: u64, output: &Path) -> std::io::Result<()> {
+    let snapshot = Snapshot {
+        tick,
+        time: world.get_time(),
+        positions: world.get_particle_positions().iter().map(|&p| p.into()).collect(),
+        velocities: world.get_particle_velocities().iter().map(|&v| v.into()).collect(),
+        color_ids: world.get_particle_color_ids(),
+        ids: world.get_particle_ids(),
+    };
+
+    let serialized = serde_json::to_string(&snapshot)?;
+
+    let mut file = File::create(output.join(format!("snapshot_{:08}.json", tick)))?;
+
+    file.write_all(serialized.as_bytes())?;
+
+    Ok(())
+}
+
+fn load_presets(args: &Args) -> std::io::Result<(WorldSettings, ParticleSettings, ColorTable)> {
+    let world_settings = match &args.world {
+        Some(name) => saver::read_world_settings(name)?,
+        None => WorldSettings::default(),
+    };
+    let particle_settings = match &args.particles {
+        Some(name) => saver::read_particle_settings(name)?,
+        None => ParticleSettings::default(),
+    };
+    let color_table = match &args.colors {
+        Some(name) => saver::read_color_table(name)?,
+        None => ColorTable::new_seeded(&ColorTable::default_colors(), world_settings.sub_seed_hash("color_table")),
+    };
+
+    Ok((world_settings, particle_settings, color_table))
+}
+
+fn run(args: Args) -> std::io::Result<()> {
+    let (world_settings, particle_settings, color_table) = load_presets(&args)?;
+
+    let time_step = args.time_step.unwrap_or(world_settings.time_step);
+
+    std::fs::create_dir_all(&args.output)?;
+
+    let mut world = World::new(&world_settings, &particle_settings, &color_table);
+    world.new_particles(&world_settings, &color_table);
+
+    if let Some(name) = &args.image {
+        let image = saver::read_image(name)?;
+
+        world.remove_particles(|_, _| true);
+        world.add_particles_from_image(&image, world_settings.max_particles, &color_table);
+    }
+
+    if let Some(name) = &args.scene {
+        world.set_scene(saver::read_scene(name)?);
+    }
+
+    let mut stats = File::create(args.output.join("stats.csv"))?;
+    writeln!(stats, "tick,partition_update_ms,velocity_update_ms,position_update_ms,tick_ms,particle_count,momentum_x,momentum_y,temperature,substeps,max_speed")?;
+
+    println!(
+        "Simulating {} particles for {} ticks (seed: '{}', time step: {}s)",
+        world.get_particle_count(), args.ticks, world_settings.seed, time_step
+    );
+
+    let start = std::time::Instant::now();
+
+    if args.snapshot_every > 0 {
+        write_snapshot(&world, 0, &args.output)?;
+    }
+
+    for tick in 1..=args.ticks {
+        let tick_start = std::time::Instant::now();
+
+        world.step(time_step, &particle_settings, &world_settings, &color_table);
+
+        let tick_time = tick_start.elapsed().as_secs_f32()*1000.0;
+
+        let momentum = world.get_total_momentum(&color_table);
+
+        writeln!(stats, "{},{:.4},{:.4},{:.4},{:.4},{},{},{},{},{},{}",
+            tick,
+            world.partition_update_time,
+            world.velocity_update_time,
+            world.position_update_time,
+            tick_time,
+            world.get_particle_count(),
+            momentum.x,
+            momentum.y,
+            world.get_temperature(&color_table),
+            world.step_stats.substeps,
+            world.step_stats.max_speed
+        )?;
+
+        if args.snapshot_every > 0 && tick % args.snapshot_every == 0 {
+            write_snapshot(&world, tick, &args.output)?;
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f32();
+
+    println!(
+        "Done in {:.2}s ({:.3}ms / tick), output written to '{}'",
+        elapsed,
+        elapsed * 1000.0 / args.ticks.max(1) as f32,
+        args.output.display()
+    );
+    let hash = world.state_hash();
+
+    println!("Final state hash: {:016x}", hash);
+
+    if let Some(expected) = args.expect_hash {
+        if hash != expected {
+            return Err(std::io::Error::other(
+                format!("Final state hash {:016x} does not match the expected {:016x}", hash, expected)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let result = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .map_err(std::io::Error::other)
+        .and_then(|pool| pool.install(|| run(args)));
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -19,7 +19,7 @@ impl Camera {
 
     pub fn zoom(&mut self, delta: f32) {
         self.scale -= delta * self.scale;
-        self.scale = self.scale.clamp((*self.scale_limits.start()).into(), (*self.scale_limits.end()).into());
+        self.scale = self.scale.clamp(*self.scale_limits.start(), *self.scale_limits.end());
     }
 
     pub fn move_xy(&mut self, delta: glm::Vec2) {
@@ -39,7 +39,7 @@ impl Camera {
     }
 
     pub fn calc_matrices(&self) -> glm::Mat4x4 {
-        let view = glm::Mat4x4::look_at_rh(&glm::Vec3::new(self.position.x, self.position.y, 1.0).into(), &glm::Vec3::new(self.position.x, self.position.y, 0.0).into(), &glm::Vec3::new(0.0, 1.0, 0.0).into());
+        let view = glm::Mat4x4::look_at_rh(&glm::Vec3::new(self.position.x, self.position.y, 1.0).into(), &glm::Vec3::new(self.position.x, self.position.y, 0.0).into(), &glm::Vec3::new(0.0, 1.0, 0.0));
         
         let size = self.size * self.scale;
 
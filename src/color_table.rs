@@ -1,23 +1,93 @@
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+
+// A particle of color `from` with a `catalyst` colored particle closer than `radius` 
+// turns into `to` with the given probability per second
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransmutationRule {
+    pub from: u8,
+    pub catalyst: u8,
+    pub to: u8,
+    pub radius: f32,
+    pub probability: f32,
+}
+
+impl Default for TransmutationRule {
+    fn default() -> Self {
+        Self {
+            from: 0,
+            catalyst: 0,
+            to: 0,
+            radius: 30.0,
+            probability: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct ColorTable {
     pub colors: Vec<glm::Vec3>,
     pub table: Vec<Vec<f32>>,
+    // Per pair influence radii, `None` falls back to the global ones from `ParticleSettings`
+    pub min_r: Vec<Vec<Option<f32>>>,
+    pub max_r: Vec<Vec<Option<f32>>>,
+    // Per color mass, particles accelerate with the pairwise forces divided by their mass
+    pub masses: Vec<f32>,
+    // Only used by the ecosystem, energy per second a particle gains for every particle of the other color within its max_r.
+    // Negative values drain the energy instead, e.g. for prey being eaten
+    pub food: Vec<Vec<f32>>,
+    // Energy per second every particle of a color loses, negative values gain it instead
+    pub metabolism: Vec<f32>,
+    // Checked in order, the first rule that fires changes the color
+    pub transmutations: Vec<TransmutationRule>,
 }
 
 impl ColorTable {
-    pub fn new(color_palette: &Vec<glm::Vec3>) -> Self {
+    pub fn new(color_palette: &[glm::Vec3]) -> Self {
+        let color_count = color_palette.len();
+
+        Self {
+            colors: color_palette.to_vec(),
+            table: Self::gen_random_table(color_count, &mut rand::thread_rng()),
+            min_r: Self::gen_global_radii(color_count),
+            max_r: Self::gen_global_radii(color_count),
+            masses: Self::gen_default_masses(color_count),
+            food: Self::gen_filled_table(color_count, 0.0),
+            metabolism: Self::gen_default_metabolism(color_count),
+            transmutations: Vec::new(),
+        }
+    }
+
+    pub fn default_colors() -> Vec<glm::Vec3> {
+        vec![
+            glm::Vec3::new(1.0, 0.1, 0.1),
+            glm::Vec3::new(0.1, 1.0, 0.1),
+            glm::Vec3::new(0.1, 0.1, 1.0),
+            glm::Vec3::new(0.5, 0.1, 1.0),
+            glm::Vec3::new(1.0, 0.1, 0.5),
+            glm::Vec3::new(1.0, 1.0, 0.1),
+        ]
+    }
+
+    pub fn new_seeded(color_palette: &[glm::Vec3], seed: u64) -> Self {
         let color_count = color_palette.len();
 
         Self {
-            colors: color_palette.clone(),
-            table: Self::gen_random_table(color_count)
+            colors: color_palette.to_vec(),
+            table: Self::gen_random_table(color_count, &mut StdRng::seed_from_u64(seed)),
+            min_r: Self::gen_global_radii(color_count),
+            max_r: Self::gen_global_radii(color_count),
+            masses: Self::gen_default_masses(color_count),
+            food: Self::gen_filled_table(color_count, 0.0),
+            metabolism: Self::gen_default_metabolism(color_count),
+            transmutations: Vec::new(),
         }
     }
 
-    fn gen_random_table(color_count: usize) -> Vec<Vec<f32>> {
+    fn gen_random_table(color_count: usize, r: &mut impl Rng) -> Vec<Vec<f32>> {
         (0..color_count).map(|_| {
             (0..color_count).map(|_| {
-                rand::thread_rng().gen_range(-1.0..=1.0)
+                r.gen_range(-1.0..=1.0)
             }).collect()
         }).collect()
     }
@@ -30,8 +100,70 @@ impl ColorTable {
         }).collect()
     }
 
+    fn gen_global_radii(color_count: usize) -> Vec<Vec<Option<f32>>> {
+        vec![vec![None; color_count]; color_count]
+    }
+
+    pub fn gen_default_masses(color_count: usize) -> Vec<f32> {
+        vec![1.0; color_count]
+    }
+
+    pub fn gen_default_metabolism(color_count: usize) -> Vec<f32> {
+        vec![0.1; color_count]
+    }
+
+    pub fn get_min_r(&self, color_id: usize, other_color_id: usize, global_min_r: f32) -> f32 {
+        self.min_r[color_id][other_color_id].unwrap_or(global_min_r)
+    }
+    pub fn get_max_r(&self, color_id: usize, other_color_id: usize, global_max_r: f32) -> f32 {
+        self.max_r[color_id][other_color_id].unwrap_or(global_max_r)
+    }
+
+    // The largest influence radius of any pair or transmutation rule, the world partitions can't be smaller than that
+    pub fn largest_max_r(&self, global_max_r: f32) -> f32 {
+        let largest = self.max_r.iter().flatten().fold(0.0, |largest: f32, max_r| {
+            largest.max(max_r.unwrap_or(global_max_r))
+        });
+
+        self.transmutations.iter().fold(largest, |largest, rule| largest.max(rule.radius))
+    }
+
+    // The smallest repulsion radius of any pair
+    pub fn smallest_min_r(&self, global_min_r: f32) -> f32 {
+        self.min_r.iter().flatten().fold(f32::INFINITY, |smallest, min_r| {
+            smallest.min(min_r.unwrap_or(global_min_r))
+        })
+    }
+
+    pub fn reset_radii(&mut self) {
+        self.min_r = Self::gen_global_radii(self.colors.len());
+        self.max_r = Self::gen_global_radii(self.colors.len());
+    }
+
+    pub fn flip(&mut self) {
+        fn flip_matrix<T: Clone>(matrix: &mut [Vec<T>]) {
+            let color_count = matrix.len();
+
+            for y in 0..color_count {
+                for x in 0..=y {
+                    let tmp = matrix[y][x].clone();
+                    matrix[y][x] = matrix[color_count-1-y][color_count-1-x].clone();
+                    matrix[color_count-1-y][color_count-1-x] = tmp;
+                }
+            }
+        }
+
+        flip_matrix(&mut self.table);
+        flip_matrix(&mut self.min_r);
+        flip_matrix(&mut self.max_r);
+        flip_matrix(&mut self.food);
+    }
+
     pub fn new_random_table(&mut self) {
-        self.table = Self::gen_random_table(self.colors.len());
+        self.table = Self::gen_random_table(self.colors.len(), &mut rand::thread_rng());
+    }
+    pub fn new_seeded_random_table(&mut self, seed: u64) {
+        self.table = Self::gen_random_table(self.colors.len(), &mut StdRng::seed_from_u64(seed));
     }
     pub fn new_filled_table(&mut self, fill: f32) {
         self.table = Self::gen_filled_table(self.colors.len(), fill);
@@ -46,11 +178,19 @@ impl ColorTable {
 
         self.colors.push(color);
 
-        self.table.iter_mut().for_each(|row| {
+        self.table.iter_mut().chain(self.food.iter_mut()).for_each(|row| {
             row.push(0.0);
         });
+        self.min_r.iter_mut().chain(self.max_r.iter_mut()).for_each(|row| {
+            row.push(None);
+        });
 
         self.table.push(vec![0.0; self.colors.len()]);
+        self.min_r.push(vec![None; self.colors.len()]);
+        self.max_r.push(vec![None; self.colors.len()]);
+        self.masses.push(1.0);
+        self.food.push(vec![0.0; self.colors.len()]);
+        self.metabolism.push(0.1);
     }
     pub fn remove_color(&mut self) {
         if self.colors.len() <= 1 {
@@ -59,10 +199,21 @@ impl ColorTable {
 
         self.colors.pop();
 
-        self.table.iter_mut().for_each(|row| {
+        self.table.iter_mut().chain(self.food.iter_mut()).for_each(|row| {
+            row.pop();
+        });
+        self.min_r.iter_mut().chain(self.max_r.iter_mut()).for_each(|row| {
             row.pop();
         });
 
         self.table.pop();
+        self.min_r.pop();
+        self.max_r.pop();
+        self.masses.pop();
+        self.food.pop();
+        self.metabolism.pop();
+
+        let color_count = self.colors.len() as u8;
+        self.transmutations.retain(|rule| rule.from < color_count && rule.catalyst < color_count && rule.to < color_count);
     }
 }
\ No newline at end of file
@@ -35,11 +35,7 @@ impl Controller {
 
     pub fn is_key_down(&mut self, keycode: Key) -> bool {
         if let Some(state) = self.key_presses.get(&keycode) {
-            if !self.last_key_presses.contains_key(&keycode) {
-                self.last_key_presses.insert(keycode, *state);
-            } else {
-                *self.last_key_presses.get_mut(&keycode).unwrap() = *state;
-            }
+            self.last_key_presses.insert(keycode, *state);
 
             *state
         } else {
@@ -51,11 +47,7 @@ impl Controller {
         if let Some(state) = self.key_presses.get(&keycode) {
             let last_state = *self.last_key_presses.get(&keycode).unwrap_or(&false);
             
-            if !self.last_key_presses.contains_key(&keycode) {
-                self.last_key_presses.insert(keycode, *state);
-            } else {
-                *self.last_key_presses.get_mut(&keycode).unwrap() = *state;
-            }
+            self.last_key_presses.insert(keycode, *state);
 
             *state != last_state && *state
         } else {
@@ -63,15 +55,21 @@ impl Controller {
         }
     }
 
+    pub fn is_button_down(&mut self, button: Button) -> bool {
+        if let Some(state) = self.button_presses.get(&button) {
+            self.last_button_presses.insert(button, *state);
+
+            *state
+        } else {
+            false
+        }
+    }
+
     pub fn is_button_pressed(&mut self, button: Button) -> bool {
         if let Some(state) = self.button_presses.get(&button) {
             let last_state = *self.last_button_presses.get(&button).unwrap_or(&false);
             
-            if !self.last_button_presses.contains_key(&button) {
-                self.last_button_presses.insert(button, *state);
-            } else {
-                *self.last_button_presses.get_mut(&button).unwrap() = *state;
-            }
+            self.last_button_presses.insert(button, *state);
 
             *state != last_state && *state
         } else {
@@ -129,11 +127,8 @@ impl Controller {
                 delta,
                 ..
             } => {
-                match delta {
-                    MouseScrollDelta::LineDelta(_, y) => {
-                        self.mouse_wheel = *y;
-                    }
-                    _ => {}
+                if let MouseScrollDelta::LineDelta(_, y) = delta {
+                    self.mouse_wheel = *y;
                 }
 
                 true
@@ -0,0 +1,163 @@
+use serde::{Serialize, Deserialize};
+
+// Scalar potential sampled on a regular grid stretched over the whole world,
+// particles accelerate down its gradient. Only the name is saved, the values are loaded from their own file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GridField {
+    pub name: String,
+    pub strength: f32,
+
+    #[serde(skip)]
+    pub width: usize,
+    #[serde(skip)]
+    pub height: usize,
+    // Row major, the first row is the bottom of the world
+    #[serde(skip)]
+    pub values: Vec<f32>,
+}
+
+impl GridField {
+    fn gradient(&self, position: &glm::Vec2, world_size: &glm::Vec2) -> glm::Vec2 {
+        if self.width < 2 || self.height < 2 || self.values.len() != self.width * self.height {
+            return glm::Vec2::zeros();
+        }
+
+        let cell_size = glm::Vec2::new(
+            world_size.x / (self.width - 1) as f32,
+            world_size.y / (self.height - 1) as f32
+        );
+
+        let grid_position = (position + world_size / 2.0).component_div(&cell_size);
+
+        let x = (grid_position.x.floor().max(0.0) as usize).min(self.width - 2);
+        let y = (grid_position.y.floor().max(0.0) as usize).min(self.height - 2);
+        let (t_x, t_y) = ((grid_position.x - x as f32).clamp(0.0, 1.0), (grid_position.y - y as f32).clamp(0.0, 1.0));
+
+        let value = |x: usize, y: usize| self.values[y * self.width + x];
+        let (v00, v10, v01, v11) = (value(x, y), value(x + 1, y), value(x, y + 1), value(x + 1, y + 1));
+
+        // Exact derivative of the bilinear interpolation inside the cell
+        glm::Vec2::new(
+            ((v10 - v00) * (1.0 - t_y) + (v11 - v01) * t_y) / cell_size.x,
+            ((v01 - v00) * (1.0 - t_x) + (v11 - v10) * t_x) / cell_size.y
+        )
+    }
+}
+
+// Accelerations acting on every particle regardless of its neighbors. Fields with a radius fade out linearly towards it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ForceField {
+    Gravity { acceleration: [f32; 2] },
+    // Pulls the particles towards the position, negative strengths push them away
+    Attractor { position: [f32; 2], strength: f32, radius: f32 },
+    // Spins the particles counterclockwise around the position, negative strengths spin them clockwise
+    Vortex { position: [f32; 2], strength: f32, radius: f32 },
+    // Horizontal acceleration growing linearly with the height
+    Shear { rate: f32 },
+    Grid(GridField),
+}
+
+impl ForceField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForceField::Gravity { .. } => "Gravity",
+            ForceField::Attractor { .. } => "Attractor",
+            ForceField::Vortex { .. } => "Vortex",
+            ForceField::Shear { .. } => "Shear",
+            ForceField::Grid(_) => "Grid",
+        }
+    }
+
+    pub fn all() -> [ForceField; 5] {
+        [
+            ForceField::Gravity { acceleration: [0.0, -50.0] },
+            ForceField::Attractor { position: [0.0, 0.0], strength: 100.0, radius: 1000.0 },
+            ForceField::Vortex { position: [0.0, 0.0], strength: 100.0, radius: 1000.0 },
+            ForceField::Shear { rate: 0.05 },
+            ForceField::Grid(GridField {
+                name: String::from("Grid file name"),
+                strength: 1.0,
+                width: 0,
+                height: 0,
+                values: Vec::new(),
+            }),
+        ]
+    }
+
+    // Fields without a position return None
+    pub fn position_mut(&mut self) -> Option<&mut [f32; 2]> {
+        match self {
+            ForceField::Attractor { position, .. } | ForceField::Vortex { position, .. } => Some(position),
+            ForceField::Gravity { .. } | ForceField::Shear { .. } | ForceField::Grid(_) => None,
+        }
+    }
+
+    pub fn acceleration(&self, position: &glm::Vec2, world_size: &glm::Vec2) -> glm::Vec2 {
+        let radial = |center: &[f32; 2], radius: f32| {
+            let vec = glm::Vec2::from(*center) - position;
+            let distance = glm::length(&vec);
+
+            if distance == 0.0 || distance >= radius {
+                None
+            } else {
+                Some((vec / distance, 1.0 - distance / radius))
+            }
+        };
+
+        match self {
+            ForceField::Gravity { acceleration } => glm::Vec2::from(*acceleration),
+            ForceField::Attractor { position: center, strength, radius } => match radial(center, *radius) {
+                Some((direction, falloff)) => direction * *strength * falloff,
+                None => glm::Vec2::zeros(),
+            },
+            ForceField::Vortex { position: center, strength, radius } => match radial(center, *radius) {
+                Some((direction, falloff)) => glm::Vec2::new(direction.y, -direction.x) * *strength * falloff,
+                None => glm::Vec2::zeros(),
+            },
+            ForceField::Shear { rate } => glm::Vec2::new(position.y * *rate, 0.0),
+            ForceField::Grid(grid) => -grid.gradient(position, world_size) * grid.strength,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InteractionKind {
+    Attract,
+    Repel,
+    // Counterclockwise for positive strengths
+    Swirl,
+    // Pulls the particles' velocities towards the interaction's own velocity
+    Drag,
+}
+
+// Short lived force applied by the user, e.g. with a mouse brush. Fades out linearly towards the radius
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interaction {
+    pub kind: InteractionKind,
+    pub position: glm::Vec2,
+    pub velocity: glm::Vec2,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl Interaction {
+    pub fn acceleration(&self, position: &glm::Vec2, velocity: &glm::Vec2) -> glm::Vec2 {
+        let vec = self.position - position;
+        let distance = glm::length(&vec);
+
+        if distance >= self.radius {
+            return glm::Vec2::zeros();
+        }
+
+        let falloff = 1.0 - distance / self.radius;
+        let direction = if distance > 0.0 { vec / distance } else { glm::Vec2::zeros() };
+
+        match self.kind {
+            InteractionKind::Attract => direction * self.strength * falloff,
+            InteractionKind::Repel => -direction * self.strength * falloff,
+            InteractionKind::Swirl => glm::Vec2::new(direction.y, -direction.x) * self.strength * falloff,
+            // The strength is scaled down, so the same slider range works for all of the kinds
+            InteractionKind::Drag => (self.velocity - velocity) * (self.strength * 0.05 * falloff),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::f32::consts::PI;
+use serde::{Serialize, Deserialize};
+use wide::{f32x8, CmpGe, CmpGt, CmpLt};
+
+// Number of particle pairs evaluated at once by `ForceKernel::force_lanes`, 8 fills an AVX register. 
+// Without AVX enabled (e.g. by `-C target-cpu=native`) every operation is split into two SSE ones
+pub const LANES: usize = 8;
+
+pub type Lanes = f32x8;
+
+// Describes how strongly two particles `distance` apart attract each other.
+// `attraction` is the color table entry for the pair, positive values pull the particles together.
+pub trait ForceKernel {
+    // Shape of the attraction between `min_r` (t = 0) and `max_r` (t = 1)
+    fn attraction(&self, t: f32) -> f32;
+
+    fn force(&self, distance: f32, min_r: f32, max_r: f32, attraction: f32) -> f32 {
+        if distance > min_r {
+            attraction * self.attraction(((distance - min_r) / (max_r - min_r)).min(1.0))
+        } else {
+            distance / min_r - 1.0
+        }
+    }
+
+    // `force` for `LANES` pairs at once. The built-in kernels override it with a branchless version in SIMD instructions, 
+    // other kernels fall back to evaluating the lanes one by one
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        let (distance, min_r, max_r, attraction) = (distance.to_array(), min_r.to_array(), max_r.to_array(), attraction.to_array());
+
+        Lanes::from(std::array::from_fn::<f32, LANES, _>(|lane| self.force(distance[lane], min_r[lane], max_r[lane], attraction[lane])))
+    }
+}
+
+// The default `ForceKernel::force` in lanes, with the attraction shaped by `shape`
+fn shaped_force_lanes(distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes, shape: impl Fn(Lanes) -> Lanes) -> Lanes {
+    let one = Lanes::splat(1.0);
+
+    let attract = attraction * shape(((distance - min_r) / (max_r - min_r)).min(one));
+    let repel = distance / min_r - one;
+
+    distance.cmp_gt(min_r).blend(attract, repel)
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tent;
+
+impl ForceKernel for Tent {
+    fn attraction(&self, t: f32) -> f32 {
+        1.0 - (2.0 * t - 1.0).abs()
+    }
+
+    // https://www.desmos.com/calculator/xjmwts0q8l
+    fn force(&self, distance: f32, min_r: f32, max_r: f32, attraction: f32) -> f32 {
+        if distance > min_r {
+            let min_r_norm = min_r / max_r;
+
+            attraction * (1.0 - (1.0 + min_r_norm - 2.0 * (distance/max_r).min(1.0)).abs() / (1.0 - min_r_norm))
+        } else {
+            distance / min_r - 1.0
+        }
+    }
+
+    // Both sides of `force` are computed for every lane and then selected, with exactly the same operations
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        let (one, two) = (Lanes::splat(1.0), Lanes::splat(2.0));
+
+        let min_r_norm = min_r / max_r;
+
+        let attract = attraction * (one - (one + min_r_norm - two * (distance/max_r).min(one)).abs() / (one - min_r_norm));
+        let repel = distance / min_r - one;
+
+        distance.cmp_gt(min_r).blend(attract, repel)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sine;
+
+impl ForceKernel for Sine {
+    fn attraction(&self, t: f32) -> f32 {
+        (PI * t).sin()
+    }
+
+    // The sine of `wide` is a polynomial approximation, so the results differ from `force` in the last bits
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        shaped_force_lanes(distance, min_r, max_r, attraction, |t| (Lanes::splat(PI) * t).sin())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gaussian {
+    pub width: f32,
+}
+
+impl Default for Gaussian {
+    fn default() -> Self {
+        Self { width: 0.2 }
+    }
+}
+
+impl Gaussian {
+    fn bell(&self, t: f32) -> f32 {
+        (-0.5 * ((t - 0.5) / self.width).powi(2)).exp()
+    }
+}
+
+impl ForceKernel for Gaussian {
+    fn attraction(&self, t: f32) -> f32 {
+        let edge = self.bell(0.0);
+
+        // Shifted and rescaled so the curve starts and ends at 0 like the others
+        ((self.bell(t) - edge) / (1.0 - edge)).max(0.0)
+    }
+
+    // Like `Sine`, the exponential of `wide` differs from the scalar one in the last bits
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        let (zero, half, one) = (Lanes::splat(0.0), Lanes::splat(0.5), Lanes::splat(1.0));
+        let (width, edge) = (Lanes::splat(self.width), Lanes::splat(self.bell(0.0)));
+
+        shaped_force_lanes(distance, min_r, max_r, attraction, |t| {
+            let x = (t - half) / width;
+            let bell = (-half * (x * x)).exp();
+
+            ((bell - edge) / (one - edge)).max(zero)
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LennardJones {
+    // Limits the repulsion at very short distances, where the real potential goes to infinity
+    pub max_repulsion: f32,
+}
+
+impl Default for LennardJones {
+    fn default() -> Self {
+        Self { max_repulsion: 2.0 }
+    }
+}
+
+impl ForceKernel for LennardJones {
+    fn attraction(&self, t: f32) -> f32 {
+        // The whole curve is defined in `force`, this only maps it onto a nominal `min_r` = 1, `max_r` = 2
+        self.force(1.0 + t, 1.0, 2.0, 1.0)
+    }
+
+    fn force(&self, distance: f32, min_r: f32, max_r: f32, attraction: f32) -> f32 {
+        if distance >= max_r {
+            return 0.0;
+        }
+
+        // The equilibrium distance sits at `min_r` and the curve is scaled so the strongest attraction equals 1
+        let r2 = (min_r / distance) * (min_r / distance);
+        let x = r2 * (r2 * r2);
+        let lj = 4.0 * (x - x * x);
+
+        if lj > 0.0 {
+            attraction * lj * (1.0 - distance / max_r)
+        } else {
+            lj.max(-self.max_repulsion)
+        }
+    }
+
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        let (zero, one, four) = (Lanes::splat(0.0), Lanes::splat(1.0), Lanes::splat(4.0));
+
+        let r2 = (min_r / distance) * (min_r / distance);
+        let x = r2 * (r2 * r2);
+        let lj = four * (x - x * x);
+
+        let attract = attraction * lj * (one - distance / max_r);
+        let repel = lj.max(Lanes::splat(-self.max_repulsion));
+
+        distance.cmp_ge(max_r).blend(zero, lj.cmp_gt(zero).blend(attract, repel))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Smoothstep {
+    // Position of the strongest attraction between `min_r` (0) and `max_r` (1)
+    pub peak: f32,
+}
+
+impl Default for Smoothstep {
+    fn default() -> Self {
+        Self { peak: 0.5 }
+    }
+}
+
+impl ForceKernel for Smoothstep {
+    fn attraction(&self, t: f32) -> f32 {
+        let smoothstep = |x: f32| {
+            let x = x.clamp(0.0, 1.0);
+            x * x * (3.0 - 2.0 * x)
+        };
+
+        if t < self.peak {
+            smoothstep(t / self.peak)
+        } else {
+            smoothstep((1.0 - t) / (1.0 - self.peak))
+        }
+    }
+
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        let (zero, one, two, three) = (Lanes::splat(0.0), Lanes::splat(1.0), Lanes::splat(2.0), Lanes::splat(3.0));
+        let peak = Lanes::splat(self.peak);
+
+        let smoothstep = |x: Lanes| {
+            let x = x.max(zero).min(one);
+            x * x * (three - two * x)
+        };
+
+        shaped_force_lanes(distance, min_r, max_r, attraction, |t| {
+            t.cmp_lt(peak).blend(smoothstep(t / peak), smoothstep((one - t) / (one - peak)))
+        })
+    }
+}
+
+// Serializable selection of one of the built-in kernels
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Kernel {
+    Tent(Tent),
+    Sine(Sine),
+    Gaussian(Gaussian),
+    LennardJones(LennardJones),
+    Smoothstep(Smoothstep),
+}
+
+impl Kernel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Tent(_) => "Tent",
+            Kernel::Sine(_) => "Sine",
+            Kernel::Gaussian(_) => "Gaussian",
+            Kernel::LennardJones(_) => "Lennard-Jones",
+            Kernel::Smoothstep(_) => "Smoothstep",
+        }
+    }
+
+    pub fn all() -> [Kernel; 5] {
+        [
+            Kernel::Tent(Tent),
+            Kernel::Sine(Sine),
+            Kernel::Gaussian(Gaussian::default()),
+            Kernel::LennardJones(LennardJones::default()),
+            Kernel::Smoothstep(Smoothstep::default()),
+        ]
+    }
+}
+
+impl Kernel {
+    // Whether the parameters are in the range the GUI allows, outside of it some curves turn into NaN. 
+    // Presets aren't limited by the GUI, so they're checked with this when loaded
+    pub fn is_valid(&self) -> bool {
+        let in_range = match self {
+            Kernel::Tent(_) | Kernel::Sine(_) => true,
+            Kernel::Gaussian(kernel) => kernel.width > 0.0 && kernel.width.is_finite(),
+            Kernel::LennardJones(kernel) => kernel.max_repulsion > 0.0 && kernel.max_repulsion.is_finite(),
+            Kernel::Smoothstep(kernel) => kernel.peak > 0.0 && kernel.peak < 1.0,
+        };
+
+        in_range && (0..=100).all(|i| self.attraction(i as f32 / 100.0).is_finite())
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Kernel::Tent(Tent)
+    }
+}
+
+impl ForceKernel for Kernel {
+    fn attraction(&self, t: f32) -> f32 {
+        match self {
+            Kernel::Tent(kernel) => kernel.attraction(t),
+            Kernel::Sine(kernel) => kernel.attraction(t),
+            Kernel::Gaussian(kernel) => kernel.attraction(t),
+            Kernel::LennardJones(kernel) => kernel.attraction(t),
+            Kernel::Smoothstep(kernel) => kernel.attraction(t),
+        }
+    }
+
+    fn force(&self, distance: f32, min_r: f32, max_r: f32, attraction: f32) -> f32 {
+        match self {
+            Kernel::Tent(kernel) => kernel.force(distance, min_r, max_r, attraction),
+            Kernel::Sine(kernel) => kernel.force(distance, min_r, max_r, attraction),
+            Kernel::Gaussian(kernel) => kernel.force(distance, min_r, max_r, attraction),
+            Kernel::LennardJones(kernel) => kernel.force(distance, min_r, max_r, attraction),
+            Kernel::Smoothstep(kernel) => kernel.force(distance, min_r, max_r, attraction),
+        }
+    }
+
+    fn force_lanes(&self, distance: Lanes, min_r: Lanes, max_r: Lanes, attraction: Lanes) -> Lanes {
+        match self {
+            Kernel::Tent(kernel) => kernel.force_lanes(distance, min_r, max_r, attraction),
+            Kernel::Sine(kernel) => kernel.force_lanes(distance, min_r, max_r, attraction),
+            Kernel::Gaussian(kernel) => kernel.force_lanes(distance, min_r, max_r, attraction),
+            Kernel::LennardJones(kernel) => kernel.force_lanes(distance, min_r, max_r, attraction),
+            Kernel::Smoothstep(kernel) => kernel.force_lanes(distance, min_r, max_r, attraction),
+        }
+    }
+}
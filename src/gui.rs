@@ -1,15 +1,98 @@
 use ::egui::FontDefinitions;
 use egui_winit_platform::{Platform, PlatformDescriptor};
 use egui::{FullOutput, ClippedPrimitive};
+use egui::plot::{Plot, Line, PlotPoints, Legend};
+
+use std::collections::VecDeque;
 
 use winit::{window::Window, event::Event};
 
-use crate::color_table::ColorTable;
-use crate::particle_settings::ParticleSettings;
+use particle_life::{saver, ColorTable, TransmutationRule, ForceField, InteractionKind, Obstacle, Scene, ParticleSettings, Integrator, ForceKernel, Kernel, WorldSettings, ParticleWrapping, Thermostat, Annealing, SpawnPattern, Image};
+
 use crate::renderer::MAX_INSTANCES;
-use crate::saver;
-use crate::world_settings::{WorldSettings, ParticleWrapping};
+use crate::simulation::SimulationStats;
+
+// Which of the color table's matrices is edited in the UI
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ColorTableMatrix {
+    Attraction,
+    MinRadius,
+    MaxRadius,
+    Food,
+}
+
+// How many samples of the population plot are kept
+const POPULATION_HISTORY_LENGTH: usize = 2000;
+
+// What happens while the left mouse button is held over the world
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BrushTool {
+    None,
+    Spawn,
+    Erase,
+    // Moves the force field at `Brush::field_index`
+    PlaceField,
+    PlaceObstacle,
+    EraseObstacle,
+    Attract,
+    Repel,
+    Swirl,
+    Drag,
+}
+
+impl BrushTool {
+    // Tools affecting everything within the brush radius
+    pub fn uses_radius(&self) -> bool {
+        !matches!(self, BrushTool::None | BrushTool::PlaceField | BrushTool::PlaceObstacle)
+    }
 
+    pub fn interaction(&self) -> Option<InteractionKind> {
+        match self {
+            BrushTool::Attract => Some(InteractionKind::Attract),
+            BrushTool::Repel => Some(InteractionKind::Repel),
+            BrushTool::Swirl => Some(InteractionKind::Swirl),
+            BrushTool::Drag => Some(InteractionKind::Drag),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObstacleShape {
+    // Dragged from the center to the edge
+    Circle,
+    // Dragged from one corner to the opposite one
+    Box,
+    // Every click adds a point, right click finishes the line
+    Polyline,
+}
+
+pub struct Brush {
+    pub tool: BrushTool,
+    pub radius: f32,
+    // Acceleration of the interaction tools at the center of the brush
+    pub strength: f32,
+    // Particles per second spawned by `BrushTool::Spawn`
+    pub rate: f32,
+    // Color of the spawned particles, or the only color erased. Any color if None
+    pub color_id: Option<u8>,
+    pub field_index: Option<usize>,
+    pub obstacle_shape: ObstacleShape,
+    pub obstacle_thickness: f32,
+}
+
+impl Brush {
+    // Obstacle of the current shape dragged between the two points
+    pub fn dragged_obstacle(&self, from: glm::Vec2, to: glm::Vec2) -> Obstacle {
+        match self.obstacle_shape {
+            ObstacleShape::Circle => Obstacle::Circle { center: from.into(), radius: glm::distance(&from, &to) },
+            ObstacleShape::Box => Obstacle::Box { min: glm::min2(&from, &to).into(), max: glm::max2(&from, &to).into() },
+            ObstacleShape::Polyline => Obstacle::Polyline { points: vec![from.into(), to.into()], thickness: self.obstacle_thickness },
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct GUI {
     platform: Platform,
 
@@ -22,6 +105,22 @@ pub struct GUI {
     particle_settings_failed: bool,
 
     color_table_fill_val: f32,
+    color_table_matrix: ColorTableMatrix,
+
+    brush: Brush,
+    grid_field_failed: bool,
+
+    scene_name: String,
+    scene_failed: bool,
+
+    image_name: String,
+    image_failed: bool,
+    image_spawn_count: usize,
+    image_spawn_replace: bool,
+    image_to_spawn: Option<Image>,
+
+    // Tick and the number of particles of every color at that tick
+    population_history: VecDeque<(u64, Vec<usize>)>,
 }
 
 impl GUI {
@@ -29,8 +128,8 @@ impl GUI {
         let size = window.inner_size();
 
         let platform = Platform::new(PlatformDescriptor {
-            physical_width: size.width as u32,
-            physical_height: size.height as u32,
+            physical_width: size.width,
+            physical_height: size.height,
             scale_factor: window.scale_factor(),
             font_definitions: FontDefinitions::default(),
             style: Default::default(),
@@ -47,30 +146,292 @@ impl GUI {
             color_table_failed: false,
             particle_settings_failed: false,
             color_table_fill_val: 0.0,
+            color_table_matrix: ColorTableMatrix::Attraction,
+
+            brush: Brush {
+                tool: BrushTool::None,
+                radius: 100.0,
+                strength: 1000.0,
+                rate: 500.0,
+                color_id: None,
+                field_index: None,
+                obstacle_shape: ObstacleShape::Box,
+                obstacle_thickness: 20.0,
+            },
+            grid_field_failed: false,
+
+            scene_name: String::from("Save file name"),
+            scene_failed: false,
+
+            image_name: String::from("Image file name"),
+            image_failed: false,
+            image_spawn_count: 10000,
+            image_spawn_replace: true,
+            image_to_spawn: None,
+
+            population_history: VecDeque::with_capacity(POPULATION_HISTORY_LENGTH),
+        }
+    }
+
+    pub fn brush(&self) -> &Brush {
+        &self.brush
+    }
+
+    // The image loaded with the "Spawn" button since the last call, with the particle count and whether to replace the current particles
+    pub fn take_image_spawn(&mut self) -> Option<(Image, usize, bool)> {
+        self.image_to_spawn.take().map(|image| (image, self.image_spawn_count, self.image_spawn_replace))
+    }
+
+    // True when the mouse is over one of the windows, so clicks shouldn't reach the world
+    pub fn wants_pointer_input(&self) -> bool {
+        self.platform.context().wants_pointer_input()
+    }
+
+    fn randomize_color_table(world_settings: &WorldSettings, color_table: &mut ColorTable) {
+        if world_settings.deterministic {
+            color_table.new_seeded_random_table(world_settings.sub_seed_hash("color_table"));
+        } else {
+            color_table.new_random_table();
+        }
+    }
+
+    fn edit_thermal(ui: &mut egui::Ui, world_settings: &mut WorldSettings) {
+        let thermal = &mut world_settings.thermal;
+
+        ui.add(egui::Slider::new(&mut thermal.temperature, 0.0..=50000.0).logarithmic(true).text("Temperature"));
+
+        ui.checkbox(&mut thermal.noise, "Brownian noise");
+        ui.label("Random kicks balanced by the drag, without much drag they keep heating the particles up unless a thermostat is used");
+
+        ui.label("Thermostat:");
+        ui.horizontal_wrapped(|ui| {
+            for thermostat in Thermostat::all() {
+                ui.radio_value(&mut thermal.thermostat, thermostat, thermostat.name());
+            }
+        });
+
+        if thermal.thermostat == Thermostat::Berendsen {
+            ui.add(egui::Slider::new(&mut thermal.coupling_time, 0.01..=10.0).logarithmic(true).text("Coupling Time"));
+        }
+
+        ui.label("Annealing:");
+        ui.horizontal_wrapped(|ui| {
+            for annealing in Annealing::all() {
+                ui.radio_value(&mut thermal.annealing, annealing, annealing.name());
+            }
+        });
+
+        if thermal.annealing != Annealing::Constant {
+            ui.add(egui::Slider::new(&mut thermal.final_temperature, 0.0..=50000.0).logarithmic(true).text("Final Temperature"));
+            ui.add(egui::Slider::new(&mut thermal.annealing_time, 1.0..=600.0).logarithmic(true).text("Annealing Time"));
+        }
+    }
+
+    fn edit_spawn_pattern(ui: &mut egui::Ui, world_settings: &mut WorldSettings, color_table: &ColorTable) {
+        ui.label("Spawn Pattern:");
+        ui.label("Applied when the particles are respawned");
+
+        egui::ComboBox::from_label("Pattern")
+            .selected_text(world_settings.spawn_pattern.name())
+            .show_ui(ui, |ui| {
+                for pattern in SpawnPattern::all() {
+                    let selected = pattern.name() == world_settings.spawn_pattern.name();
+
+                    if ui.selectable_label(selected, pattern.name()).clicked() && !selected {
+                        world_settings.spawn_pattern = pattern;
+                    }
+                }
+            });
+
+        match &mut world_settings.spawn_pattern {
+            SpawnPattern::Clusters { count, spread } => {
+                ui.add(egui::Slider::new(count, 1..=64).text("Clusters"));
+                ui.add(egui::Slider::new(spread, 10.0..=2000.0).text("Spread"));
+            }
+            SpawnPattern::Rings { width } => {
+                ui.add(egui::Slider::new(width, 0.0..=1000.0).text("Ring Width"));
+            }
+            SpawnPattern::Stripes { vertical } => {
+                ui.checkbox(vertical, "Vertical");
+            }
+            SpawnPattern::Disk { radius } => {
+                ui.add(egui::Slider::new(radius, 0.01..=1.0).text("Relative Radius"));
+            }
+            SpawnPattern::Lattice { jitter } => {
+                ui.add(egui::Slider::new(jitter, 0.0..=1.0).text("Jitter"));
+            }
+            SpawnPattern::Uniform | SpawnPattern::Segregated => (),
+        }
+
+        ui.label("Species Ratios:");
+        ui.horizontal_wrapped(|ui| {
+            for (color_id, color) in color_table.colors.iter().enumerate() {
+                let mut ratio = world_settings.species_ratios.get(color_id).copied().unwrap_or(1.0);
+                let text_color = egui::Color32::from_rgb((color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8);
+
+                ui.colored_label(text_color, "■");
+                if ui.add(egui::DragValue::new(&mut ratio).clamp_range(0.0..=100.0).speed(0.05)).changed() {
+                    // Empty ratios mean equal ones, so only fill them in once they're edited
+                    world_settings.species_ratios.resize(color_table.colors.len(), 1.0);
+                    world_settings.species_ratios[color_id] = ratio;
+                }
+            }
+        });
+
+        if ui.button("Equal ratios").clicked() {
+            world_settings.species_ratios.clear();
+        }
+    }
+
+    fn plot_kernel(ui: &mut egui::Ui, particle_settings: &ParticleSettings) {
+        let max_distance = particle_settings.max_r * 1.1;
+
+        let curve = |attraction: f32| {
+            PlotPoints::new((0..=200).map(|i| {
+                let distance = max_distance * i as f32 / 200.0;
+                let force = particle_settings.kernel.force(distance, particle_settings.min_r, particle_settings.max_r, attraction);
+
+                [distance as f64, force as f64]
+            }).collect())
+        };
+
+        Plot::new("Force kernel plot")
+            .height(120.0)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .include_y(-1.0)
+            .include_y(1.0)
+            .legend(Legend::default())
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(curve(1.0)).name("Attraction = 1"));
+                plot_ui.line(Line::new(curve(-1.0)).name("Attraction = -1"));
+            });
+    }
+
+    fn plot_populations(&self, ui: &mut egui::Ui, color_table: &ColorTable) {
+        Plot::new("Population plot")
+            .height(120.0)
+            .width(250.0)
+            .include_y(0.0)
+            .show(ui, |plot_ui| {
+                for (color_id, color) in color_table.colors.iter().enumerate() {
+                    let points = PlotPoints::new(self.population_history.iter().map(|(tick, populations)| {
+                        [*tick as f64, populations.get(color_id).copied().unwrap_or(0) as f64]
+                    }).collect());
+
+                    plot_ui.line(Line::new(points).color(egui::Color32::from_rgb(
+                        (color.x * 255.0) as u8,
+                        (color.y * 255.0) as u8,
+                        (color.z * 255.0) as u8
+                    )));
+                }
+            });
+    }
+
+    fn edit_force_fields(&mut self, ui: &mut egui::Ui, world_settings: &mut WorldSettings) {
+        let mut removed_field = None;
+
+        for (field_index, field) in world_settings.force_fields.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(field.name());
+
+                match field {
+                    ForceField::Gravity { acceleration } => {
+                        ui.add(egui::DragValue::new(&mut acceleration[0]).speed(1.0).prefix("x: "));
+                        ui.add(egui::DragValue::new(&mut acceleration[1]).speed(1.0).prefix("y: "));
+                    }
+                    ForceField::Attractor { strength, radius, .. } | ForceField::Vortex { strength, radius, .. } => {
+                        ui.add(egui::DragValue::new(strength).speed(1.0).prefix("Strength: "));
+                        ui.add(egui::DragValue::new(radius).clamp_range(1.0..=20000.0).speed(5.0).prefix("Radius: "));
+                    }
+                    ForceField::Shear { rate } => {
+                        ui.add(egui::DragValue::new(rate).speed(0.001).fixed_decimals(3).prefix("Rate: "));
+                    }
+                    ForceField::Grid(grid) => {
+                        ui.add(egui::DragValue::new(&mut grid.strength).speed(0.01).prefix("Strength: "));
+                        ui.add(egui::TextEdit::singleline(&mut grid.name).desired_width(100.0));
+
+                        if ui.button("Load").clicked() {
+                            self.grid_field_failed = saver::read_grid_field(grid).is_err();
+                        }
+
+                        ui.label(format!("{}x{}", grid.width, grid.height));
+                    }
+                }
+
+                if let Some(position) = field.position_mut() {
+                    ui.add(egui::DragValue::new(&mut position[0]).speed(5.0).prefix("x: "));
+                    ui.add(egui::DragValue::new(&mut position[1]).speed(5.0).prefix("y: "));
+
+                    let placing = self.brush.tool == BrushTool::PlaceField && self.brush.field_index == Some(field_index);
+
+                    if ui.selectable_label(placing, "Place").clicked() {
+                        self.brush.tool = BrushTool::PlaceField;
+                        self.brush.field_index = Some(field_index);
+                    }
+                }
+
+                if ui.button("Remove").clicked() {
+                    removed_field = Some(field_index);
+                }
+            });
+        }
+
+        if let Some(field_index) = removed_field {
+            world_settings.force_fields.remove(field_index);
+
+            self.brush.field_index = None;
         }
+
+        if self.grid_field_failed {
+            ui.label("Failed to load the grid field!");
+        }
+
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Add:");
+
+            for field in ForceField::all() {
+                if ui.button(field.name()).clicked() {
+                    world_settings.force_fields.push(field);
+                }
+            }
+        });
     }
 
     pub fn handle_event(&mut self, event: &Event<()>) {
         self.platform.handle_event(event);
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_ui(&mut self, 
         world_settings: &mut WorldSettings,
         particle_settings: &mut ParticleSettings,
         color_table: &mut ColorTable,
-        fixed_time_step: &mut bool,
+        scene: &mut Scene,
         should_update_world: &mut bool,
         should_update_particles: &mut bool,
         should_update_colors: &mut bool,
         simulate: &mut bool,
-        time_step: &mut f32,
-        velocity_update_time: f32,
-        position_update_time: f32,
-        partition_update_time: f32,
         gpu_time: f32,
+        stats: &SimulationStats,
     ) -> Option<(FullOutput, Vec<ClippedPrimitive>)> {
         self.platform.begin_frame();
 
+        let tick = stats.tick;
+
+        // Starting over after a respawn
+        if self.population_history.back().is_some_and(|&(last_tick, _)| last_tick > tick) {
+            self.population_history.clear();
+        }
+        if self.population_history.back().is_none_or(|&(last_tick, _)| last_tick != tick) {
+            if self.population_history.len() == POPULATION_HISTORY_LENGTH {
+                self.population_history.pop_front();
+            }
+
+            self.population_history.push_back((tick, stats.populations.clone()));
+        }
+
         egui::Window::new(String::from("Control Panel"))
             .anchor(egui::Align2::LEFT_TOP, [5.0, 5.0])
             .show(&self.platform.context(), |ui| {
@@ -82,18 +443,40 @@ impl GUI {
                     *should_update_particles = true;
                 }
                 if ui.button("Randomize Color Table").clicked() {
-                    color_table.new_random_table();
+                    Self::randomize_color_table(world_settings, color_table);
                 }
 
                 ui.separator();
                 
                 ui.checkbox(simulate, "Simulate");
 
-                ui.checkbox(fixed_time_step, "Fixed time step");
+                ui.add(egui::Slider::new(&mut world_settings.tick_rate, 0.0..=240.0).text("[ticks/s] Tick Rate"));
+                ui.label("The simulation runs on its own thread, 0 runs it as fast as possible");
+
+                ui.add_enabled(!world_settings.deterministic, egui::Checkbox::new(&mut world_settings.fixed_time_step, "Fixed time step"));
                 ui.label("Will stabilise the simulation especially at lower FPS");
 
-                if *fixed_time_step {
-                    ui.add(egui::Slider::new(time_step, 0.0..=0.1).text("[s/tick] Time Step").fixed_decimals(3).step_by(0.002));
+                ui.checkbox(&mut world_settings.deterministic, "Deterministic");
+                ui.label("Seed, presets and tick count fully reproduce the simulation");
+
+                if world_settings.is_time_step_fixed() {
+                    ui.add(egui::Slider::new(&mut world_settings.time_step, 0.0..=0.1).text("[s/tick] Time Step").fixed_decimals(3).step_by(0.002));
+                }
+
+                ui.add(egui::Slider::new(&mut world_settings.max_step, 0.001..=0.1).text("[s] Max Substep").fixed_decimals(3));
+                ui.add(egui::Slider::new(&mut world_settings.max_substeps, 1..=64).text("Max Substeps"));
+
+                ui.checkbox(&mut world_settings.adaptive_step, "Adaptive substeps");
+                ui.label("Shorter substeps while particles move fast compared to the smallest min_r");
+
+                if world_settings.adaptive_step {
+                    ui.add(egui::Slider::new(&mut world_settings.courant, 0.05..=2.0).text("Courant Number"));
+                }
+
+                ui.checkbox(&mut world_settings.velocity_cap, "Velocity cap");
+
+                if world_settings.velocity_cap {
+                    ui.add(egui::Slider::new(&mut world_settings.max_speed, 10.0..=20000.0).logarithmic(true).text("Max Speed"));
                 }
 
                 ui.separator();
@@ -111,12 +494,12 @@ impl GUI {
                     
                     ui.label("Size:");
                     ui.horizontal(|ui| {
-                        if ui.add(egui::DragValue::new(&mut world_settings.size).clamp_range(1000.0..=10000.0)).changed() {
+                        if ui.add(egui::DragValue::new(&mut world_settings.width).clamp_range(1000.0..=10000.0)).changed() {
                             *should_update_world = true;
                         }
                         ui.label("x");
     
-                        if ui.add(egui::DragValue::new(&mut world_settings.size).clamp_range(1000.0..=10000.0)).changed() {
+                        if ui.add(egui::DragValue::new(&mut world_settings.height).clamp_range(1000.0..=10000.0)).changed() {
                             *should_update_world = true;
                         }
                     });
@@ -128,17 +511,48 @@ impl GUI {
 
                     ui.separator();
                     ui.label("Particle Count:");
-                    if ui.add(egui::Slider::new(&mut world_settings.max_particles, 0..=MAX_INSTANCES)).changed() {
-                        *should_update_particles = true;
-                    }
+                    // The world adds or removes particles to match, without respawning the others. 
+                    // The ecosystem and the brushes change the current count without moving the slider
+                    ui.add(egui::Slider::new(&mut world_settings.max_particles, 0..=MAX_INSTANCES-1));
+                    ui.label(format!("Current: {}", stats.particle_count));
+                    ui.separator();
+
+                    Self::edit_spawn_pattern(ui, world_settings, color_table);
                     ui.separator();
 
                     ui.label("Wrapping:");
-                    ui.horizontal(|ui| {
-                        ui.radio_value(&mut world_settings.wrapping, ParticleWrapping::Barrier, "Barrier");
-                        ui.radio_value(&mut world_settings.wrapping, ParticleWrapping::Wrap, "Wrap");
+                    ui.horizontal_wrapped(|ui| {
+                        for wrapping in ParticleWrapping::all() {
+                            ui.radio_value(&mut world_settings.wrapping, wrapping, wrapping.name());
+                        }
                     });
 
+                    if world_settings.wrapping == ParticleWrapping::SoftWall {
+                        ui.add(egui::Slider::new(&mut world_settings.wall_stiffness, 0.0..=5000.0).text("Wall Stiffness"));
+                        ui.add(egui::Slider::new(&mut world_settings.wall_range, 1.0..=500.0).text("Wall Range"));
+                    }
+
+                    ui.separator();
+
+                    if ui.checkbox(&mut world_settings.morton_order, "Morton order").changed() {
+                        *should_update_world = true;
+                    }
+                    ui.label("Keeps more neighboring particles close in memory, may be faster in large worlds");
+
+                    ui.separator();
+
+                    ui.checkbox(&mut world_settings.ecosystem.enabled, "Ecosystem");
+                    ui.label("Particles eat, starve and divide according to the color table's food and metabolism");
+
+                    if world_settings.ecosystem.enabled {
+                        ui.add(egui::Slider::new(&mut world_settings.ecosystem.division_energy, 1.0..=10.0).text("Division Energy"));
+                        ui.add(egui::Slider::new(&mut world_settings.ecosystem.max_population, 0..=MAX_INSTANCES-1).text("Max Population"));
+                    }
+
+                    ui.separator();
+
+                    Self::edit_thermal(ui, world_settings);
+
                     ui.separator();
 
                     if ui.button("Restore defaults").clicked() {
@@ -154,7 +568,10 @@ impl GUI {
                             saver::save_world_settings(world_settings, &self.world_settings_name).unwrap();
                         } 
                         if ui.button("Load").clicked() {
-                            if let Ok(new_world_settings) = saver::read_world_settings(&self.world_settings_name) {
+                            if let Ok(mut new_world_settings) = saver::read_world_settings(&self.world_settings_name) {
+                                new_world_settings.max_particles = new_world_settings.max_particles.min(MAX_INSTANCES - 1);
+                                new_world_settings.ecosystem.max_population = new_world_settings.ecosystem.max_population.min(MAX_INSTANCES - 1);
+
                                 *world_settings = new_world_settings;
                                 *should_update_world = true;
                                 *should_update_particles = true;
@@ -174,6 +591,75 @@ impl GUI {
 
                 ui.separator();
 
+                ui.collapsing("Force Fields", |ui| {
+                    self.edit_force_fields(ui, world_settings);
+                });
+
+                ui.separator();
+
+                ui.collapsing("Scene", |ui| {
+                    ui.label(format!("Obstacles: {}", scene.obstacles.len()));
+
+                    ui.add(egui::Slider::new(&mut scene.restitution, 0.0..=1.0).text("Restitution"));
+                    ui.add(egui::Slider::new(&mut scene.repulsion, 0.0..=5000.0).text("Repulsion"));
+                    ui.add(egui::Slider::new(&mut scene.repulsion_range, 1.0..=500.0).text("Repulsion Range"));
+
+                    if ui.button("Clear obstacles").clicked() {
+                        scene.obstacles.clear();
+                    }
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            saver::save_scene(scene, &self.scene_name).unwrap();
+                        } 
+                        if ui.button("Load").clicked() {
+                            if let Ok(new_scene) = saver::read_scene(&self.scene_name) {
+                                *scene = new_scene;
+                                self.scene_failed = false;
+                            } else {
+                                self.scene_failed = true;
+                            }
+                        }
+
+                        ui.text_edit_singleline(&mut self.scene_name);
+                    });
+
+                    if self.scene_failed {
+                        ui.label("Failed to load the scene!");
+                    }
+                });
+
+                ui.separator();
+
+                ui.collapsing("Image Spawn", |ui| {
+                    ui.label("Spawns particles from a PNG in saved/, denser where it's brighter and colored like the closest palette color");
+
+                    ui.add(egui::Slider::new(&mut self.image_spawn_count, 0..=MAX_INSTANCES-1).text("Particles"));
+                    ui.checkbox(&mut self.image_spawn_replace, "Replace the current particles");
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Spawn").clicked() {
+                            match saver::read_image(&self.image_name) {
+                                Ok(image) => {
+                                    self.image_to_spawn = Some(image);
+                                    self.image_failed = false;
+                                }
+                                Err(_) => self.image_failed = true,
+                            }
+                        }
+
+                        ui.text_edit_singleline(&mut self.image_name);
+                    });
+
+                    if self.image_failed {
+                        ui.label("Failed to load the image!");
+                    }
+                });
+
+                ui.separator();
+
                 ui.collapsing("Particle settings", |ui| {
                     if ui.add(egui::Slider::new(&mut particle_settings.max_r, particle_settings.min_r+0.1..=1000.0).text("Max influence radius")).changed() {
                         *should_update_world = true;
@@ -181,6 +667,51 @@ impl GUI {
                     ui.add(egui::Slider::new(&mut particle_settings.min_r, 10.0..=particle_settings.max_r-0.1).text("Min influence radius"));
                     ui.add(egui::Slider::new(&mut particle_settings.force, 0.0..=10.0).text("Atraction force"));
                     ui.add(egui::Slider::new(&mut particle_settings.drag, 0.0..=1.0).fixed_decimals(2).text("Velocity over time"));
+
+                    ui.separator();
+
+                    egui::ComboBox::from_label("Force kernel")
+                        .selected_text(particle_settings.kernel.name())
+                        .show_ui(ui, |ui| {
+                            for kernel in Kernel::all() {
+                                let selected = kernel.name() == particle_settings.kernel.name();
+
+                                if ui.selectable_label(selected, kernel.name()).clicked() && !selected {
+                                    particle_settings.kernel = kernel;
+                                }
+                            }
+                        });
+
+                    match &mut particle_settings.kernel {
+                        Kernel::Gaussian(kernel) => {
+                            ui.add(egui::Slider::new(&mut kernel.width, 0.05..=1.0).text("Width"));
+                        }
+                        Kernel::LennardJones(kernel) => {
+                            ui.add(egui::Slider::new(&mut kernel.max_repulsion, 0.5..=10.0).text("Max repulsion"));
+                        }
+                        Kernel::Smoothstep(kernel) => {
+                            ui.add(egui::Slider::new(&mut kernel.peak, 0.05..=0.95).text("Peak"));
+                        }
+                        Kernel::Tent(_) | Kernel::Sine(_) => {}
+                    }
+
+                    Self::plot_kernel(ui, particle_settings);
+
+                    ui.add_enabled(!particle_settings.newtonian, egui::Checkbox::new(&mut particle_settings.simd, "SIMD"));
+                    ui.label("Evaluates the forces 8 neighbors at a time, with identical results except for the Sine and Gaussian kernels' last bits. Not used by the Newtonian mode");
+
+                    ui.checkbox(&mut particle_settings.newtonian, "Newtonian");
+                    ui.label("Evaluates every pair once with equal and opposite forces, using the average of both table entries");
+
+                    ui.separator();
+
+                    ui.label("Integrator:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut particle_settings.integrator, Integrator::Euler, "Euler");
+                        ui.radio_value(&mut particle_settings.integrator, Integrator::SemiImplicitEuler, "Semi-implicit Euler");
+                        ui.radio_value(&mut particle_settings.integrator, Integrator::VelocityVerlet, "Velocity Verlet");
+                        ui.radio_value(&mut particle_settings.integrator, Integrator::RK4, "RK4");
+                    });
     
                     ui.separator();
 
@@ -216,17 +747,16 @@ impl GUI {
                 ui.separator();
 
                 ui.collapsing("Color Table", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.color_table_matrix, ColorTableMatrix::Attraction, "Attraction");
+                        ui.radio_value(&mut self.color_table_matrix, ColorTableMatrix::MinRadius, "Min radius");
+                        ui.radio_value(&mut self.color_table_matrix, ColorTableMatrix::MaxRadius, "Max radius");
+                        ui.radio_value(&mut self.color_table_matrix, ColorTableMatrix::Food, "Food");
+                    });
+
                     ui.horizontal(|ui| {
                         if ui.button("  Flip   ").clicked() {
-                            let color_count = color_table.colors.len();
-    
-                            for y in 0..color_count {
-                                for x in 0..=y {
-                                    let tmp = color_table.table[y][x];
-                                    color_table.table[y][x] = color_table.table[color_count-1-y][color_count-1-x];
-                                    color_table.table[color_count-1-y][color_count-1-x] = tmp;
-                                }
-                            }
+                            color_table.flip();
                         }
     
                         for (y_index, _) in color_table.table.iter_mut().enumerate() {
@@ -244,7 +774,9 @@ impl GUI {
                             continue;
                         }
                     });
-                    for (y_index, row) in color_table.table.iter_mut().enumerate() {
+                    let color_count = color_table.colors.len();
+
+                    for y_index in 0..color_count {
                         ui.horizontal(|ui| {
                             let color = &mut color_table.colors[y_index];
     
@@ -256,15 +788,59 @@ impl GUI {
                             
                             *color = glm::Vec3::from(rgb);
     
-                            for value in row.iter_mut() {
-                                ui.add(egui::DragValue::new(value).clamp_range(-1.0..=1.0).speed(0.1).fixed_decimals(2));
-                                if *value < 0.0 {
-                                    ui.add_space(-3.0);
+                            for x_index in 0..color_count {
+                                match self.color_table_matrix {
+                                    ColorTableMatrix::Attraction => {
+                                        let value = &mut color_table.table[y_index][x_index];
+
+                                        ui.add(egui::DragValue::new(value).clamp_range(-1.0..=1.0).speed(0.1).fixed_decimals(2));
+                                        if *value < 0.0 {
+                                            ui.add_space(-3.0);
+                                        }
+                                    }
+                                    ColorTableMatrix::MinRadius => {
+                                        let max_r = color_table.get_max_r(y_index, x_index, particle_settings.max_r);
+                                        let mut min_r = color_table.get_min_r(y_index, x_index, particle_settings.min_r);
+
+                                        if ui.add(egui::DragValue::new(&mut min_r).clamp_range(1.0..=max_r-0.1).speed(1.0).fixed_decimals(0)).changed() {
+                                            color_table.min_r[y_index][x_index] = Some(min_r);
+                                        }
+                                    }
+                                    ColorTableMatrix::MaxRadius => {
+                                        let min_r = color_table.get_min_r(y_index, x_index, particle_settings.min_r);
+                                        let mut max_r = color_table.get_max_r(y_index, x_index, particle_settings.max_r);
+
+                                        if ui.add(egui::DragValue::new(&mut max_r).clamp_range(min_r+0.1..=1000.0).speed(1.0).fixed_decimals(0)).changed() {
+                                            color_table.max_r[y_index][x_index] = Some(max_r);
+                                            *should_update_world = true;
+                                        }
+                                    }
+                                    ColorTableMatrix::Food => {
+                                        ui.add(egui::DragValue::new(&mut color_table.food[y_index][x_index]).clamp_range(-5.0..=5.0).speed(0.01).fixed_decimals(2));
+                                    }
                                 }
                             }
                         });
                     }
                 
+                    ui.horizontal(|ui| {
+                        ui.label("Mass:");
+
+                        for mass in color_table.masses.iter_mut() {
+                            ui.add(egui::DragValue::new(mass).clamp_range(0.01..=100.0).speed(0.05).fixed_decimals(2));
+                        }
+                    });
+
+                    if world_settings.ecosystem.enabled {
+                        ui.horizontal(|ui| {
+                            ui.label("Metabolism:");
+
+                            for metabolism in color_table.metabolism.iter_mut() {
+                                ui.add(egui::DragValue::new(metabolism).clamp_range(-5.0..=5.0).speed(0.01).fixed_decimals(2));
+                            }
+                        });
+                    }
+
                     ui.separator();
 
                     ui.horizontal(|ui|{
@@ -276,14 +852,59 @@ impl GUI {
                         if ui.button("Remove Color").clicked() {
                             color_table.remove_color();
                             *should_update_colors = true;
+                            *should_update_world = true;
                         }
                     });
 
                     ui.separator();
 
-                    if ui.button("Randomize").clicked() {
-                        color_table.new_random_table();
-                    }
+                    ui.collapsing("Transmutations", |ui| {
+                        let max_color_id = (color_table.colors.len() - 1) as u8;
+                        let mut removed_rule = None;
+
+                        for (rule_index, rule) in color_table.transmutations.iter_mut().enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut rule.from).clamp_range(0..=max_color_id));
+                                ui.label("near");
+                                ui.add(egui::DragValue::new(&mut rule.catalyst).clamp_range(0..=max_color_id));
+                                ui.label("becomes");
+                                ui.add(egui::DragValue::new(&mut rule.to).clamp_range(0..=max_color_id));
+                                ui.label("within");
+                                if ui.add(egui::DragValue::new(&mut rule.radius).clamp_range(1.0..=1000.0).speed(1.0).fixed_decimals(0)).changed() {
+                                    *should_update_world = true;
+                                }
+                                ui.label("[1/s]");
+                                ui.add(egui::DragValue::new(&mut rule.probability).clamp_range(0.0..=1.0).speed(0.01).fixed_decimals(2));
+
+                                if ui.button("Remove").clicked() {
+                                    removed_rule = Some(rule_index);
+                                }
+                            });
+                        }
+
+                        if let Some(rule_index) = removed_rule {
+                            color_table.transmutations.remove(rule_index);
+                            *should_update_world = true;
+                        }
+
+                        if ui.button("Add rule").clicked() {
+                            color_table.transmutations.push(TransmutationRule::default());
+                            *should_update_world = true;
+                        }
+                    });
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Randomize").clicked() {
+                            Self::randomize_color_table(world_settings, color_table);
+                        }
+
+                        if ui.button("Reset radii to global").clicked() {
+                            color_table.reset_radii();
+                            *should_update_world = true;
+                        }
+                    });
 
                     ui.horizontal(|ui| {
                         if ui.button("Fill").clicked() {
@@ -303,6 +924,7 @@ impl GUI {
                             if let Ok(new_color_table) = saver::read_color_table(&self.color_table_name) {
                                 *color_table = new_color_table;
                                 *should_update_colors = true;
+                                *should_update_world = true;
                                 self.color_table_failed = false;
                             } else {
                                 self.color_table_failed = true;
@@ -319,6 +941,62 @@ impl GUI {
 
                 ui.separator();
 
+                ui.collapsing("Brush", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.brush.tool, BrushTool::None, "None");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Spawn, "Spawn");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Erase, "Erase");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::PlaceField, "Place Field");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.brush.tool, BrushTool::PlaceObstacle, "Place Obstacle");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::EraseObstacle, "Erase Obstacle");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Attract, "Attract");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Repel, "Repel");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Swirl, "Swirl");
+                        ui.radio_value(&mut self.brush.tool, BrushTool::Drag, "Drag");
+                    });
+
+                    if self.brush.tool == BrushTool::PlaceObstacle {
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.brush.obstacle_shape, ObstacleShape::Circle, "Circle");
+                            ui.radio_value(&mut self.brush.obstacle_shape, ObstacleShape::Box, "Box");
+                            ui.radio_value(&mut self.brush.obstacle_shape, ObstacleShape::Polyline, "Polyline");
+                        });
+
+                        if self.brush.obstacle_shape == ObstacleShape::Polyline {
+                            ui.add(egui::Slider::new(&mut self.brush.obstacle_thickness, 1.0..=200.0).text("Thickness"));
+                        }
+                    }
+
+                    ui.add(egui::Slider::new(&mut self.brush.radius, 10.0..=1000.0).text("Radius"));
+                    ui.add(egui::Slider::new(&mut self.brush.strength, -5000.0..=5000.0).text("Strength"));
+                    ui.add(egui::Slider::new(&mut self.brush.rate, 10.0..=5000.0).text("[particles/s] Spawn Rate"));
+
+                    ui.label("Color:");
+                    ui.horizontal_wrapped(|ui| {
+                        ui.radio_value(&mut self.brush.color_id, None, "Any");
+
+                        for (color_id, color) in color_table.colors.iter().enumerate() {
+                            let text = egui::RichText::new(format!("{}", color_id)).color(egui::Color32::from_rgb(
+                                (color.x * 255.0) as u8,
+                                (color.y * 255.0) as u8,
+                                (color.z * 255.0) as u8
+                            ));
+
+                            ui.radio_value(&mut self.brush.color_id, Some(color_id as u8), text);
+                        }
+                    });
+
+                    if self.brush.color_id.is_some_and(|color_id| color_id as usize >= color_table.colors.len()) {
+                        self.brush.color_id = None;
+                    }
+                });
+
+                ui.separator();
+
                 ui.collapsing("Rendering", |ui| {
                     ui.add(egui::Slider::new(&mut particle_settings.radius, 1.0..=60.0).text("Particle Radius"));
                     ui.add(egui::Slider::new(&mut particle_settings.sharpness, 0.0..=0.999).text("Particle Sharpness"));
@@ -331,16 +1009,41 @@ impl GUI {
                     ui.label("Shift + U - Toggle UI");
                     ui.label("Shift + LMB - Follow a particle");
                     ui.label("Shift + RMB - Stop following");
+                    ui.label("LMB - Use the brush");
+                    ui.label("RMB - Finish the polyline obstacle");
                 });
             });
 
         egui::Window::new(String::from("Metrics"))
             .anchor(egui::Align2::RIGHT_TOP, [-5.0, 5.0])
             .show(&self.platform.context(), |ui| {
-                ui.label(format!("Velocity update time: {:.2}ms", velocity_update_time));
-                ui.label(format!("Position update time: {:.2}ms", position_update_time));
-                ui.label(format!("Partition update time: {:.2}ms", partition_update_time));
+                let step_stats = &stats.step_stats;
+
+                ui.label(format!("Velocity update time: {:.2}ms", stats.velocity_update_time));
+                ui.label(format!("Position update time: {:.2}ms", stats.position_update_time));
+                ui.label(format!("Partition update time: {:.2}ms", stats.partition_update_time));
                 ui.label(format!("GPU time: {:.2}ms", gpu_time));
+                ui.label(format!("Simulation: {:.0} ticks/s", stats.ticks_per_second));
+                ui.label(format!("Tick: {}", tick));
+                ui.label(format!("Substeps: {} ({:.2}ms each)", step_stats.substeps, step_stats.substep_size*1000.0));
+
+                if step_stats.dropped_time > 0.0 {
+                    ui.label(format!("Dropped time: {:.2}ms", step_stats.dropped_time*1000.0));
+                }
+
+                ui.label(format!("Max speed: {:.1}", step_stats.max_speed));
+                ui.label(format!("Capped particles: {}", step_stats.capped_particles));
+                ui.label(format!("Momentum: ({:.1}, {:.1})", stats.momentum.x, stats.momentum.y));
+
+                if let Some(target_temperature) = stats.target_temperature {
+                    ui.label(format!("Temperature: {:.1} (target {:.1})", stats.temperature, target_temperature));
+                } else {
+                    ui.label(format!("Temperature: {:.1}", stats.temperature));
+                }
+
+                ui.collapsing("Population", |ui| {
+                    self.plot_populations(ui, color_table);
+                });
             });
     
         let full_output = self.platform.end_frame(None);
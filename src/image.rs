@@ -0,0 +1,40 @@
+// RGBA image with components in 0..=1, row major with the first row at the top
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl Image {
+    // Perceived brightness of the pixel, transparent pixels are dark
+    pub fn brightness(&self, x: usize, y: usize) -> f32 {
+        let [r, g, b, a] = self.pixels[y * self.width + x];
+
+        (0.2126 * r + 0.7152 * g + 0.0722 * b) * a
+    }
+
+    // Index of the color closest to the pixel's color
+    pub fn nearest_color(&self, x: usize, y: usize, colors: &[glm::Vec3]) -> u8 {
+        let [r, g, b, _] = self.pixels[y * self.width + x];
+        let pixel = glm::Vec3::new(r, g, b);
+
+        colors.iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| glm::distance2(a, &pixel).total_cmp(&glm::distance2(b, &pixel)))
+            .map_or(0, |(color_id, _)| color_id as u8)
+    }
+
+    // The largest rectangle centered in `half_size` with the image's aspect ratio, as its min and max corners
+    pub fn fitted_rect(&self, half_size: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let aspect = self.width.max(1) as f32 / self.height.max(1) as f32;
+
+        let fitted = if half_size.x / half_size.y > aspect {
+            glm::Vec2::new(half_size.y * aspect, half_size.y)
+        } else {
+            glm::Vec2::new(half_size.x, half_size.x / aspect)
+        };
+
+        (-fitted, fitted)
+    }
+}
@@ -0,0 +1,45 @@
+//! Particle Life simulation core.
+//!
+//! Everything needed to run and save/load a simulation lives here, the windowed
+//! front-end (`gui` feature) is just one consumer of this crate.
+//!
+//! ```no_run
+//! use particle_life::{World, WorldSettings, ParticleSettings, ColorTable};
+//!
+//! let world_settings = WorldSettings::default();
+//! let particle_settings = ParticleSettings::default();
+//! let color_table = ColorTable::new(&ColorTable::default_colors());
+//!
+//! let mut world = World::new(&world_settings, &particle_settings, &color_table);
+//! world.new_particles(&world_settings, &color_table);
+//!
+//! for _ in 0..100 {
+//!     world.step(0.016, &particle_settings, &world_settings, &color_table);
+//! }
+//! ```
+
+pub extern crate nalgebra_glm as glm;
+
+pub mod world;
+pub mod force_kernel;
+pub mod force_field;
+pub mod obstacle;
+pub mod spawn_pattern;
+pub mod image;
+pub mod stable_hasher;
+
+pub mod saver;
+
+pub mod world_settings;
+pub mod particle_settings;
+pub mod color_table;
+
+pub use world::{World, StepStats};
+pub use world_settings::{WorldSettings, ParticleWrapping, EcosystemSettings, ThermalSettings, Thermostat, Annealing};
+pub use particle_settings::{ParticleSettings, Integrator};
+pub use force_kernel::{ForceKernel, Kernel, Lanes, LANES};
+pub use force_field::{ForceField, GridField, Interaction, InteractionKind};
+pub use obstacle::{Obstacle, Scene};
+pub use spawn_pattern::SpawnPattern;
+pub use image::Image;
+pub use color_table::{ColorTable, TransmutationRule};
@@ -0,0 +1,31 @@
+struct PushConstants {
+    proj_view: mat4x4<f32>,
+};
+
+var<push_constant> pc: PushConstants;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) fpos: vec4<f32>,
+
+    @location(0) fcolor: vec4<f32>,
+};
+
+@vertex
+fn vs_main(vert: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.fpos = pc.proj_view * vec4<f32>(vert.position, 0.0, 1.0);
+    out.fcolor = vert.color;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.fcolor;
+}
@@ -12,40 +12,43 @@ mod gui;
 mod camera;
 mod renderer;
 mod controller;
-mod world;
+mod simulation;
 
-mod saver;
-
-mod world_settings;
-mod particle_settings;
-mod color_table;
-
-use world_settings::WorldSettings;
-use world::World;
-use particle_settings::ParticleSettings;
-use color_table::ColorTable;
-use gui::GUI;
+use particle_life::{World, WorldSettings, ParticleSettings, ColorTable, Scene, Obstacle, Interaction};
+use gui::{GUI, BrushTool, ObstacleShape};
 use camera::Camera;
 use renderer::{Renderer, MAX_INSTANCES, MAX_COLORS};
 use controller::{Controller, Key, Button};
+use simulation::{Simulation, Command};
 
 struct Game {
     renderer: Renderer,
     camera: Camera,
     controller: Controller,
 
-    time_step: f32,
-    fixed_time_step: bool,
     last_frame_time: std::time::SystemTime,
 
     simulate: bool,
 
-    world: World,
+    // The world itself lives on the simulation thread, these are the render thread's copies sent to it whenever they change
+    simulation: Simulation,
     world_settings: WorldSettings, 
     particle_settings: ParticleSettings, 
     color_table: ColorTable, 
+    scene: Scene,
+    // Applied by the simulation until it's cleared
+    interaction: Option<Interaction>,
 
-    followed_index: Option<usize>,
+    followed_id: Option<usize>,
+
+    // Fraction of a particle left over from the last frames of spawning with the brush
+    brush_spawn_budget: f32,
+    // Where the mouse was pressed while dragging out a new obstacle
+    obstacle_drag_start: Option<glm::Vec2>,
+    // Clicks add points to the last obstacle while this is set
+    building_polyline: bool,
+    // Used to find out how fast the mouse moves for the drag brush
+    last_mouse_position: glm::Vec2,
 
     show_ui: bool
 }
@@ -56,20 +59,12 @@ impl Game {
 
         let camera = Camera::new(1.0..=20.0);
         
-        let colors = vec![
-            glm::Vec3::new(1.0, 0.1, 0.1),
-            glm::Vec3::new(0.1, 1.0, 0.1),
-            glm::Vec3::new(0.1, 0.1, 1.0),
-            glm::Vec3::new(0.5, 0.1, 1.0),
-            glm::Vec3::new(1.0, 0.1, 0.5),
-            glm::Vec3::new(1.0, 1.0, 0.1),
-        ];
-
-        let color_table = ColorTable::new(&colors);
+        let color_table = ColorTable::new(&ColorTable::default_colors());
 
         let world_settings = WorldSettings {
             max_particles: 4096*2,
-            size: 5000.0,
+            width: 5000.0,
+            height: 5000.0,
             ..Default::default()
         };
 
@@ -80,10 +75,13 @@ impl Game {
         assert!(color_table.colors.len() < MAX_COLORS);
         assert!(world_settings.max_particles < MAX_INSTANCES);
 
-        let mut world = World::new(&world_settings, &particle_settings);
+        let mut world = World::new(&world_settings, &particle_settings, &color_table);
 
         world.new_particles(&world_settings, &color_table);
 
+        let scene = world.get_scene().clone();
+        let simulation = Simulation::spawn(world, &world_settings, &particle_settings, &color_table, true);
+
         let renderer = Renderer::new(window, &color_table.colors).await;
     
         Self {
@@ -91,18 +89,23 @@ impl Game {
             camera,
             controller,
 
-            time_step: 0.0,
-            fixed_time_step: false,
             last_frame_time: std::time::SystemTime::now(),
 
             simulate: true,
 
-            world,
+            simulation,
             world_settings,
             particle_settings,
             color_table,
+            scene,
+            interaction: None,
+
+            followed_id: None,
 
-            followed_index: None,
+            brush_spawn_budget: 0.0,
+            obstacle_drag_start: None,
+            building_polyline: false,
+            last_mouse_position: glm::Vec2::zeros(),
 
             show_ui: true
         }
@@ -121,19 +124,116 @@ impl Game {
         self.controller.process_input(event)
     }
 
-    fn update(&mut self) {
-        let delta_time = self.last_frame_time.elapsed().unwrap().as_secs_f32();
+    fn mouse_world_position(&self) -> glm::Vec2 {
+        let ndc: glm::Vec2 = glm::Vec2::new(
+            self.controller.mouse_position.0 as f32 / self.camera.size.x * 2.0 - 1.0,
+            (1.0 - self.controller.mouse_position.1 as f32 / self.camera.size.y) * 2.0 - 1.0
+        );
+
+        self.camera.viewport_to_world(ndc)
+    }
+
+    fn apply_brush(&mut self, gui: &GUI, delta_time: f32) {
+        let brush = gui.brush();
+        let position = self.mouse_world_position();
+
+        let pressed = self.controller.is_button_pressed(Button::Left);
+        let down = self.controller.is_button_down(Button::Left);
+
+        if brush.tool != BrushTool::PlaceObstacle {
+            self.obstacle_drag_start = None;
+            self.building_polyline = false;
+        }
+
+        match brush.tool {
+            BrushTool::Spawn if down => {
+                self.brush_spawn_budget += brush.rate * delta_time;
+
+                let count = self.brush_spawn_budget as usize;
+                self.brush_spawn_budget = self.brush_spawn_budget.fract();
 
-        if !self.fixed_time_step {
-            self.time_step = delta_time;
+                if count > 0 {
+                    self.simulation.send(Command::SpawnInCircle { center: position, radius: brush.radius, count, color_id: brush.color_id });
+                }
+            }
+            BrushTool::Erase if down => {
+                self.simulation.send(Command::EraseInCircle { center: position, radius: brush.radius, color_id: brush.color_id });
+            }
+            BrushTool::PlaceField if down => {
+                if let Some(field_position) = brush.field_index
+                    .and_then(|index| self.world_settings.force_fields.get_mut(index))
+                    .and_then(|field| field.position_mut()) {
+                    *field_position = position.into();
+                }
+            }
+            BrushTool::PlaceObstacle if brush.obstacle_shape == ObstacleShape::Polyline => {
+                if pressed {
+                    match (self.building_polyline, self.scene.obstacles.last_mut()) {
+                        (true, Some(Obstacle::Polyline { points, .. })) => points.push(position.into()),
+                        _ => {
+                            self.scene.obstacles.push(Obstacle::Polyline { 
+                                points: vec![position.into()], 
+                                thickness: brush.obstacle_thickness 
+                            });
+                            self.building_polyline = true;
+                        }
+                    }
+                }
+                if self.controller.is_button_pressed(Button::Right) {
+                    self.building_polyline = false;
+                }
+            }
+            BrushTool::PlaceObstacle => {
+                if let Some(start) = self.obstacle_drag_start {
+                    if !down {
+                        self.scene.obstacles.push(brush.dragged_obstacle(start, position));
+                        self.obstacle_drag_start = None;
+                    }
+                } else if pressed {
+                    self.obstacle_drag_start = Some(position);
+                }
+            }
+            BrushTool::EraseObstacle if down => {
+                self.scene.obstacles.retain(|obstacle| obstacle.signed_distance(&position).0 > brush.radius);
+            }
+            BrushTool::Attract | BrushTool::Repel | BrushTool::Swirl | BrushTool::Drag if down => {
+                // Applied until the brush is released
+                self.interaction = brush.tool.interaction().map(|kind| Interaction {
+                    kind,
+                    position,
+                    velocity: (position - self.last_mouse_position) / delta_time.max(0.001),
+                    radius: brush.radius,
+                    strength: brush.strength,
+                });
+            }
+            _ => {}
         }
+    }
+
+    fn update(&mut self, gui: &GUI) {
+        let delta_time = self.last_frame_time.elapsed().unwrap().as_secs_f32();
 
         self.last_frame_time = std::time::SystemTime::now();
 
+        self.simulation.poll();
+
+        self.simulation.interpolate(glm::Vec2::new(self.world_settings.width, self.world_settings.height) * 0.5);
+
         self.camera.zoom(self.controller.mouse_wheel * 0.025);
 
-        if let Some(followed_index) = self.followed_index {
-            self.camera.move_towards(3.0 * delta_time, self.world.get_particle_position(followed_index));
+        if let Some(picked_id) = self.simulation.take_picked_id() {
+            self.followed_id = picked_id;
+        }
+
+        // The followed particle is tracked by its id, since its index changes whenever the world reorders the particles
+        let followed_index = self.followed_id.and_then(|id| self.simulation.get_particle_index(id));
+
+        if followed_index.is_none() {
+            self.followed_id = None;
+        }
+
+        if let Some(followed_index) = followed_index {
+            self.camera.move_towards(3.0 * delta_time, self.simulation.positions()[followed_index]);
         } else {
             let camera_direction = glm::Vec2::new(
                 self.controller.get_axis(Key::A, Key::D),
@@ -143,80 +243,105 @@ impl Game {
             self.camera.move_xy(camera_direction * 400.0 * delta_time);
         }
 
-        if self.simulate {
-            self.world.update_partitions();
-
-            self.world.update_particles(self.time_step, &self.particle_settings, &self.world_settings, &self.color_table);
-        }
+        self.interaction = None;
 
         if self.controller.is_key_down(Key::LShift) {
             if self.controller.is_button_pressed(Button::Left) {
-                let ndc: glm::Vec2 = glm::Vec2::new(
-                    self.controller.mouse_position.0 as f32 / self.camera.size.x * 2.0 - 1.0,
-                    (1.0 - self.controller.mouse_position.1 as f32 / self.camera.size.y) * 2.0 - 1.0
-                );
-    
-                if let Some(id) = self.world.get_closest_particle_id(&self.camera.viewport_to_world(ndc)) {
-                    self.followed_index = Some(id);
-                }
+                self.simulation.pick(&self.mouse_world_position());
             } else if self.controller.is_button_pressed(Button::Right) {
-                self.followed_index = None;
+                self.followed_id = None;
             }
 
             if self.controller.is_key_pressed(Key::U) {
                 self.show_ui = !self.show_ui;
             }
+        } else if !gui.wants_pointer_input() {
+            self.apply_brush(gui, delta_time);
         }
 
+        self.last_mouse_position = self.mouse_world_position();
+
         self.controller.update();
     }
 
+    fn sync_simulation(&mut self) {
+        self.simulation.sync(&self.world_settings, &self.particle_settings, &self.color_table, &self.scene, self.simulate, self.interaction);
+    }
+
     fn render(&mut self, gui: &mut GUI) -> Result<(), wgpu::SurfaceError> {
-        for (&position, &color_id) in self.world.get_particle_positions().iter().zip(self.world.get_particle_color_ids().iter()) {
+        let snapshot = self.simulation.snapshot();
+
+        for ((&position, &color_id), &radius_scale) in self.simulation.positions().iter().zip(&snapshot.color_ids).zip(&snapshot.radius_scales) {
             self.renderer.enqueue_instance(renderer::Instance {
-                position: position,
-                color_id: color_id as u32
+                position,
+                color_id: color_id as u32,
+                radius_scale
             });
         }
 
+        for obstacle in &self.scene.obstacles {
+            for (from, to) in obstacle.outline() {
+                self.renderer.enqueue_line(from, to, [0.8, 0.8, 0.8, 1.0]);
+            }
+        }
+
+        if gui.brush().tool.uses_radius() && !gui.wants_pointer_input() {
+            self.renderer.enqueue_circle(self.mouse_world_position(), gui.brush().radius, [1.0, 1.0, 1.0, 0.5]);
+        }
+
+        if let (Some(start), BrushTool::PlaceObstacle) = (self.obstacle_drag_start, gui.brush().tool) {
+            for (from, to) in gui.brush().dragged_obstacle(start, self.mouse_world_position()).outline() {
+                self.renderer.enqueue_line(from, to, [0.8, 0.8, 0.8, 0.4]);
+            }
+        }
+
         let frame_data = if self.show_ui {
             let mut should_update_world = false;
             let mut should_update_particles = false;
             let mut should_update_colors = false;
 
+            let max_particles = self.world_settings.max_particles;
+
             let data = gui.draw_ui(
                 &mut self.world_settings,
                 &mut self.particle_settings,
                 &mut self.color_table,
-                &mut self.fixed_time_step,
+                &mut self.scene,
                 &mut should_update_world,
                 &mut should_update_particles,
                 &mut should_update_colors,
                 &mut self.simulate,
-                &mut self.time_step,
-                self.world.velocity_update_time,
-                self.world.position_update_time,
-                self.world.partition_update_time,
-                self.renderer.gpu_time
+                self.renderer.gpu_time,
+                &snapshot.stats
             );
 
+            // The new settings have to arrive before the commands using them
+            self.sync_simulation();
+
             if should_update_world {
-                self.world.new_partitions(&self.world_settings, &self.particle_settings);
+                self.simulation.send(Command::NewPartitions);
+            }
+            if self.world_settings.max_particles != max_particles {
+                self.simulation.send(Command::SetParticleCount(self.world_settings.max_particles));
             }
             if should_update_colors {
-                self.world.clamp_particle_colors(&self.color_table);
+                self.simulation.send(Command::ClampColors);
                 self.renderer.update_colors(&self.color_table.colors);
             }
             if should_update_particles {
-                self.world.new_particles(&self.world_settings, &self.color_table);
+                self.simulation.send(Command::Respawn);
+            }
+            if let Some((image, count, replace)) = gui.take_image_spawn() {
+                self.simulation.send(Command::SpawnImage { image, count, replace });
             }
-
 
             data
         } else {
             None
         };
 
+        self.sync_simulation();
+
         let result = self.renderer.render(
             &self.world_settings.bg_color,
             self.camera.calc_matrices(),
@@ -251,7 +376,7 @@ async fn run() {
         
         match event {
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                game.update();
+                game.update(&gui);
 
                 match game.render(&mut gui) {
                     Ok(_) => {}
@@ -274,15 +399,13 @@ async fn run() {
                 window_id
             } 
 
-            if window_id == window.id() => if !game.input(event) {
-                match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            if window_id == window.id() && !game.input(event) => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
 
-                    WindowEvent::Resized(physical_size) => {
-                        game.resize(*physical_size, window.scale_factor());
-                    }
-                    _ => {}
+                WindowEvent::Resized(physical_size) => {
+                    game.resize(*physical_size, window.scale_factor());
                 }
+                _ => {}
             }
             _ => {}
         }
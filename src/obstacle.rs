@@ -0,0 +1,129 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Obstacle {
+    Circle { center: [f32; 2], radius: f32 },
+    // Axis aligned
+    Box { min: [f32; 2], max: [f32; 2] },
+    // Connected line segments with rounded ends
+    Polyline { points: Vec<[f32; 2]>, thickness: f32 },
+}
+
+impl Obstacle {
+    // Distance from the surface, negative inside, and the direction pointing out of the obstacle
+    pub fn signed_distance(&self, position: &glm::Vec2) -> (f32, glm::Vec2) {
+        let from_point = |point: glm::Vec2, radius: f32| {
+            let vec = position - point;
+            let distance = glm::length(&vec);
+
+            if distance == 0.0 {
+                (-radius, glm::Vec2::new(1.0, 0.0))
+            } else {
+                (distance - radius, vec / distance)
+            }
+        };
+
+        match self {
+            Obstacle::Circle { center, radius } => from_point(glm::Vec2::from(*center), *radius),
+            Obstacle::Box { min, max } => {
+                let (min, max) = (glm::Vec2::from(*min), glm::Vec2::from(*max));
+                let center = (min + max) / 2.0;
+                let half_size = (max - min) / 2.0;
+
+                let relative = position - center;
+                let q = glm::abs(&relative) - half_size;
+                let sign = glm::Vec2::new(relative.x.signum(), relative.y.signum());
+
+                if q.x > 0.0 || q.y > 0.0 {
+                    let outside = glm::max(&q, 0.0);
+                    let distance = glm::length(&outside);
+
+                    (distance, outside.component_mul(&sign) / distance)
+                } else if q.x > q.y {
+                    (q.x, glm::Vec2::new(sign.x, 0.0))
+                } else {
+                    (q.y, glm::Vec2::new(0.0, sign.y))
+                }
+            }
+            Obstacle::Polyline { points, thickness } => {
+                let mut closest = (f32::INFINITY, glm::Vec2::new(1.0, 0.0));
+
+                for segment in points.windows(2) {
+                    let (start, end) = (glm::Vec2::from(segment[0]), glm::Vec2::from(segment[1]));
+                    let direction = end - start;
+
+                    let length_sq = glm::dot(&direction, &direction);
+                    let t = if length_sq > 0.0 {
+                        (glm::dot(&(position - start), &direction) / length_sq).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+
+                    let candidate = from_point(start + direction * t, thickness / 2.0);
+
+                    if candidate.0 < closest.0 {
+                        closest = candidate;
+                    }
+                }
+
+                if let [point] = points.as_slice() {
+                    closest = from_point(glm::Vec2::from(*point), thickness / 2.0);
+                }
+
+                closest
+            }
+        }
+    }
+
+    // Line segments tracing the obstacle, e.g. for drawing it
+    pub fn outline(&self) -> Vec<(glm::Vec2, glm::Vec2)> {
+        match self {
+            Obstacle::Circle { center, radius } => {
+                let center = glm::Vec2::from(*center);
+                let point = |i: usize| {
+                    let angle = i as f32 / 48.0 * std::f32::consts::TAU;
+
+                    center + glm::Vec2::new(angle.cos(), angle.sin()) * *radius
+                };
+
+                (0..48).map(|i| (point(i), point(i + 1))).collect()
+            }
+            Obstacle::Box { min, max } => {
+                let corners = [
+                    glm::Vec2::new(min[0], min[1]),
+                    glm::Vec2::new(max[0], min[1]),
+                    glm::Vec2::new(max[0], max[1]),
+                    glm::Vec2::new(min[0], max[1]),
+                ];
+
+                (0..4).map(|i| (corners[i], corners[(i + 1) % 4])).collect()
+            }
+            Obstacle::Polyline { points, .. } => {
+                points.windows(2).map(|segment| (glm::Vec2::from(segment[0]), glm::Vec2::from(segment[1]))).collect()
+            }
+        }
+    }
+}
+
+// Static geometry inside the world, saved separately from the settings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub obstacles: Vec<Obstacle>,
+    // Part of the velocity along the surface normal kept after a collision, 0 stops the particles and 1 is fully elastic
+    pub restitution: f32,
+    // Acceleration pushing the particles away from the surface, fading out over `repulsion_range`. 0 disables it
+    pub repulsion: f32,
+    pub repulsion_range: f32,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            obstacles: Vec::new(),
+            restitution: 0.5,
+            repulsion: 0.0,
+            repulsion_range: 50.0,
+        }
+    }
+}
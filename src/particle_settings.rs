@@ -1,11 +1,30 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use crate::force_kernel::Kernel;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    Euler,
+    SemiImplicitEuler,
+    VelocityVerlet,
+    RK4,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ParticleSettings {
     pub max_r: f32,
     pub min_r: f32,
     pub force: f32,
     pub drag: f32,
+    pub integrator: Integrator,
+    pub kernel: Kernel,
+    // Evaluates the pair forces `LANES` neighbors at a time. The results are identical either way, 
+    // except for the kernels built on a sine or an exponential, whose SIMD versions are approximations.
+    // Not used by the Newtonian mode
+    pub simd: bool,
+    // Evaluates every pair once with the symmetrised table, so both particles get equal and opposite forces and momentum is conserved
+    pub newtonian: bool,
 
     pub radius: f32,
     pub sharpness: f32,
@@ -19,6 +38,10 @@ impl Default for ParticleSettings {
             min_r: 50.0,
             force: 5.0,
             drag: 0.06813,
+            integrator: Integrator::SemiImplicitEuler,
+            kernel: Kernel::default(),
+            simd: true,
+            newtonian: false,
             radius: 20.0,
             sharpness: 0.8,
             bloom: 8.0,
@@ -34,6 +34,7 @@ const INDICES: &[u32] = &[
 
 pub const MAX_INSTANCES: usize = 50_000;
 pub const MAX_COLORS: usize = 50;
+pub const MAX_LINES: usize = 20_000;
 
 impl Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -52,6 +53,35 @@ impl Vertex {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
+struct LineVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ]
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
 struct ColorRaw {
@@ -63,6 +93,7 @@ struct ColorRaw {
 struct InstanceRaw {
     position: [f32; 2],
     color_id: u32,
+    radius_scale: f32,
 }
 
 impl InstanceRaw {
@@ -83,6 +114,11 @@ impl InstanceRaw {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                wgpu::VertexAttribute {
+                    offset: (mem::size_of::<[f32; 2]>() + mem::size_of::<u32>()) as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
@@ -91,14 +127,17 @@ impl InstanceRaw {
 #[derive(Clone)]
 pub struct Instance {
     pub position: glm::Vec2,
-    pub color_id: u32
+    pub color_id: u32,
+    // Multiplies the particle radius from the settings
+    pub radius_scale: f32,
 }
 
 impl Instance {
     fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             position: self.position.into(),
-            color_id: self.color_id
+            color_id: self.color_id,
+            radius_scale: self.radius_scale,
         }
     }
 }
@@ -115,6 +154,7 @@ pub struct Renderer {
     pub gpu_time: f32,
 
     pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
 
     vertex_buffer: wgpu::Buffer,
     index_buffer:  wgpu::Buffer,
@@ -122,6 +162,9 @@ pub struct Renderer {
     instances: Vec<InstanceRaw>,
     instance_buffer: wgpu::Buffer,
 
+    line_vertices: Vec<LineVertex>,
+    line_buffer: wgpu::Buffer,
+
     colors_buffer: wgpu::Buffer,
     colors_bind_group: wgpu::BindGroup,
 
@@ -238,6 +281,57 @@ impl Renderer {
             multiview: None,
         });
 
+        let line_shader = device.create_shader_module(wgpu::include_wgsl!("line.wgsl"));
+
+        let line_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Line Render Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::VERTEX,
+                range: 0..std::mem::size_of::<[[f32; 4]; 4]>() as u32,
+            }],
+        });
+
+        let line_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Line Render Pipeline"),
+            layout: Some(&line_pipeline_layout),
+
+            vertex: wgpu::VertexState {
+                module: &line_shader,
+                entry_point: "vs_main", 
+                buffers: &[LineVertex::desc()],
+            },
+
+            fragment: Some(wgpu::FragmentState {
+                module: &line_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+
+            depth_stencil: None,
+
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Vertex Buffer"),
@@ -258,7 +352,8 @@ impl Renderer {
         let alloc_data = (0..MAX_INSTANCES).map(|_|{
             Instance {
                position: glm::Vec2::identity(), 
-               color_id: 0
+               color_id: 0,
+               radius_scale: 1.0,
             }
         }).collect::<Vec<_>>();
 
@@ -272,6 +367,17 @@ impl Renderer {
             }
         );
 
+        let line_vertices = Vec::with_capacity(MAX_LINES * 2);
+
+        let line_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Line Buffer"),
+                contents: bytemuck::cast_slice(&vec![LineVertex::default(); MAX_LINES * 2]),
+
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
         let mut colors_data: Vec<ColorRaw> = colors.iter().map(|&c|{
             ColorRaw{color: [c.x, c.y, c.z, 0.0]}
         }).collect();
@@ -313,6 +419,7 @@ impl Renderer {
             gpu_time: 1.0,
 
             pipeline,
+            line_pipeline,
 
             vertex_buffer,
             index_buffer,
@@ -320,6 +427,9 @@ impl Renderer {
             instances,
             instance_buffer,
 
+            line_vertices,
+            line_buffer,
+
             colors_buffer,
             colors_bind_group,
 
@@ -329,6 +439,27 @@ impl Renderer {
 
     pub fn reset_queue(&mut self) {
         self.instances.clear();
+        self.line_vertices.clear();
+    }
+
+    pub fn enqueue_circle(&mut self, center: glm::Vec2, radius: f32, color: [f32; 4]) {
+        let point = |i: usize| {
+            let angle = i as f32 / 48.0 * std::f32::consts::TAU;
+
+            center + glm::Vec2::new(angle.cos(), angle.sin()) * radius
+        };
+
+        for i in 0..48 {
+            self.enqueue_line(point(i), point(i + 1), color);
+        }
+    }
+
+    // Lines over the MAX_LINES limit are silently dropped
+    pub fn enqueue_line(&mut self, from: glm::Vec2, to: glm::Vec2, color: [f32; 4]) {
+        if self.line_vertices.len() < MAX_LINES * 2 {
+            self.line_vertices.push(LineVertex { position: from.into(), color });
+            self.line_vertices.push(LineVertex { position: to.into(), color });
+        }
     }
 
     pub fn enqueue_instance(&mut self, instance: Instance) {
@@ -372,6 +503,7 @@ impl Renderer {
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default()); 
 
         self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.instances));
+        self.queue.write_buffer(&self.line_buffer, 0, bytemuck::cast_slice(&self.line_vertices));
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
@@ -412,19 +544,27 @@ impl Renderer {
             }));
 
             render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..self.instances.len() as u32);
+
+            if !self.line_vertices.is_empty() {
+                let proj_view: [[f32; 4]; 4] = proj_view.into();
+
+                render_pass.set_pipeline(&self.line_pipeline);
+                render_pass.set_vertex_buffer(0, self.line_buffer.slice(..));
+                render_pass.set_push_constants(wgpu::ShaderStages::VERTEX, 0, bytemuck::bytes_of(&proj_view));
+
+                render_pass.draw(0..self.line_vertices.len() as u32, 0..1);
+            }
         }
 
         let start;
 
-        if frame_data.is_some() {
+        if let Some((full_output, paint_jobs)) = frame_data {
             let screen_descriptor = ScreenDescriptor {
                 physical_width: self.config.width,
                 physical_height: self.config.height,
                 scale_factor: self.scale_factor as f32,
             };
     
-            let (full_output, paint_jobs) = frame_data.unwrap();
-    
             self.egui_render_pass.add_textures(&self.device, &self.queue, &full_output.textures_delta).unwrap();
             self.egui_render_pass.update_buffers(&self.device, &self.queue, &paint_jobs, &screen_descriptor);
 
@@ -1,21 +1,45 @@
-use crate::color_table::ColorTable;
+use crate::color_table::{ColorTable, TransmutationRule};
 use crate::particle_settings::ParticleSettings;
 use crate::world_settings::WorldSettings;
+use crate::force_field::{ForceField, GridField};
+use crate::obstacle::Scene;
+use crate::image::Image;
 
 use serde::{Serialize, Deserialize};
 
 use std::fs::File;
 use std::io::prelude::*;
 
+#[derive(Serialize, Deserialize)]
+struct GridFieldProxy {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct ColorTableProxy {
     pub colors: Vec<[f32; 3]>,
     pub table: Vec<Vec<f32>>,
+    // Older color tables don't have per pair radii
+    #[serde(default)]
+    pub min_r: Vec<Vec<Option<f32>>>,
+    #[serde(default)]
+    pub max_r: Vec<Vec<Option<f32>>>,
+    // Older color tables don't have masses
+    #[serde(default)]
+    pub masses: Vec<f32>,
+    #[serde(default)]
+    pub food: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub metabolism: Vec<f32>,
+    #[serde(default)]
+    pub transmutations: Vec<TransmutationRule>,
 }
 
-fn read_file(name: &String) -> std::io::Result<String> {
+fn read_file(name: &str) -> std::io::Result<String> {
     let mut path = String::from("saved/");
-    path.push_str(name.as_str());
+    path.push_str(name);
 
     if !path.contains(".json") {
         path.push_str(".json");
@@ -30,7 +54,7 @@ fn read_file(name: &String) -> std::io::Result<String> {
     Ok(contents)
 }
 
-fn save_file(data: &String, name: &String) -> std::io::Result<()> {
+fn save_file(data: &str, name: &str) -> std::io::Result<()> {
     let path_exists = std::path::Path::new("saved/").exists();
 
     if !path_exists {
@@ -38,7 +62,7 @@ fn save_file(data: &String, name: &String) -> std::io::Result<()> {
     }
     
     let mut path = String::from("saved/");
-    path.push_str(name.as_str());
+    path.push_str(name);
 
     if !path.contains(".json") {
         path.push_str(".json");
@@ -46,12 +70,12 @@ fn save_file(data: &String, name: &String) -> std::io::Result<()> {
 
     let mut file = File::create(path)?;
 
-    file.write(data.as_bytes())?;
+    file.write_all(data.as_bytes())?;
 
     Ok(())
 }
 
-pub fn save_particle_settings(particle_settings: &ParticleSettings, name: &String) -> std::io::Result<()> {
+pub fn save_particle_settings(particle_settings: &ParticleSettings, name: &str) -> std::io::Result<()> {
     let serialized = serde_json::to_string(&particle_settings)?;
 
     save_file(&serialized, name)?;
@@ -59,19 +83,31 @@ pub fn save_particle_settings(particle_settings: &ParticleSettings, name: &Strin
     Ok(())
 }
 
-pub fn read_particle_settings(name: &String) -> std::io::Result<ParticleSettings> {
-    let serialized = read_file(name)?;
+pub fn read_particle_settings(name: &str) -> std::io::Result<ParticleSettings> {
+    parse_particle_settings(&read_file(name)?)
+}
 
-    let deserialized = serde_json::from_str(&serialized)?;
+pub fn parse_particle_settings(serialized: &str) -> std::io::Result<ParticleSettings> {
+    let deserialized: ParticleSettings = serde_json::from_str(serialized)?;
+
+    if !deserialized.kernel.is_valid() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Kernel parameters out of range"));
+    }
 
     Ok(deserialized)
 }
 
-pub fn save_color_table(color_table: &ColorTable, name: &String) -> std::io::Result<()> {
+pub fn save_color_table(color_table: &ColorTable, name: &str) -> std::io::Result<()> {
     // Serde cant serialize glm::Vec3 so I have to convert those Vec3s to [f32; 3]
     let color_table_proxy = ColorTableProxy {
         colors: color_table.colors.iter().map(|&c| c.into()).collect(),
         table: color_table.table.clone(),
+        min_r: color_table.min_r.clone(),
+        max_r: color_table.max_r.clone(),
+        masses: color_table.masses.clone(),
+        food: color_table.food.clone(),
+        metabolism: color_table.metabolism.clone(),
+        transmutations: color_table.transmutations.clone(),
     };
     
     let serialized = serde_json::to_string(&color_table_proxy)?;
@@ -81,20 +117,57 @@ pub fn save_color_table(color_table: &ColorTable, name: &String) -> std::io::Res
     Ok(())
 }
 
-pub fn read_color_table(name: &String) -> std::io::Result<ColorTable> {
-    let serialized = read_file(name)?;
+// Whether the table has an entry for every pair of colors
+fn is_square<T>(table: &[Vec<T>], color_count: usize) -> bool {
+    table.len() == color_count && table.iter().all(|row| row.len() == color_count)
+}
 
-    let deserialized: ColorTableProxy = serde_json::from_str(&serialized)?;
+pub fn read_color_table(name: &str) -> std::io::Result<ColorTable> {
+    parse_color_table(&read_file(name)?)
+}
 
-    let color_table = ColorTable {
+pub fn parse_color_table(serialized: &str) -> std::io::Result<ColorTable> {
+    let deserialized: ColorTableProxy = serde_json::from_str(serialized)?;
+
+    let mut color_table = ColorTable {
         colors: deserialized.colors.iter().map(|&c| glm::Vec3::from(c)).collect(),
         table: deserialized.table.clone(),
+        min_r: deserialized.min_r,
+        max_r: deserialized.max_r,
+        masses: deserialized.masses,
+        food: deserialized.food,
+        metabolism: deserialized.metabolism,
+        transmutations: deserialized.transmutations,
     };
 
+    let color_count = color_table.colors.len();
+
+    // Missing from older color tables
+    if color_table.min_r.is_empty() || color_table.max_r.is_empty() {
+        color_table.reset_radii();
+    }
+    if color_table.masses.is_empty() {
+        color_table.masses = ColorTable::gen_default_masses(color_count);
+    }
+    if color_table.food.is_empty() || color_table.metabolism.is_empty() {
+        color_table.food = vec![vec![0.0; color_count]; color_count];
+        color_table.metabolism = ColorTable::gen_default_metabolism(color_count);
+    }
+
+    // Everything is indexed by color id inside the force loop, where a missing entry would panic
+    if !is_square(&color_table.table, color_count) || !is_square(&color_table.min_r, color_count) || !is_square(&color_table.max_r, color_count)
+        || !is_square(&color_table.food, color_count) || color_table.masses.len() != color_count || color_table.metabolism.len() != color_count {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Color table entries don't match the number of colors"));
+    }
+    // The forces are divided by the masses
+    if color_table.masses.iter().any(|&mass| !(mass.is_finite() && mass > 0.0)) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Masses have to be positive"));
+    }
+
     Ok(color_table)
 }
 
-pub fn save_world_settings(world_settings: &WorldSettings, name: &String) -> std::io::Result<()> {
+pub fn save_world_settings(world_settings: &WorldSettings, name: &str) -> std::io::Result<()> {
     let serialized = serde_json::to_string(&world_settings)?;
 
     save_file(&serialized, name)?;
@@ -102,10 +175,98 @@ pub fn save_world_settings(world_settings: &WorldSettings, name: &String) -> std
     Ok(())
 }
 
-pub fn read_world_settings(name: &String) -> std::io::Result<WorldSettings> {
+pub fn read_world_settings(name: &str) -> std::io::Result<WorldSettings> {
+    let serialized = read_file(name)?;
+
+    let mut deserialized = parse_world_settings(&serialized)?;
+
+    for field in deserialized.force_fields.iter_mut() {
+        if let ForceField::Grid(grid) = field {
+            read_grid_field(grid)?;
+        }
+    }
+
+    Ok(deserialized)
+}
+
+// Also accepts presets saved by older versions, without loading the values of grid fields
+pub fn parse_world_settings(serialized: &str) -> std::io::Result<WorldSettings> {
+    let mut value: serde_json::Value = serde_json::from_str(serialized)?;
+
+    // Worlds used to always be square with a single `size`
+    if let Some(size) = value.get("size").cloned() {
+        value["width"] = size.clone();
+        value["height"] = size;
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+// Loads the values of a grid field from the file named after it
+pub fn read_grid_field(grid: &mut GridField) -> std::io::Result<()> {
+    let serialized = read_file(&grid.name)?;
+
+    let deserialized: GridFieldProxy = serde_json::from_str(&serialized)?;
+
+    if deserialized.values.len() != deserialized.width * deserialized.height {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Grid field size doesn't match its values"));
+    }
+
+    grid.width = deserialized.width;
+    grid.height = deserialized.height;
+    grid.values = deserialized.values;
+
+    Ok(())
+}
+
+pub fn save_scene(scene: &Scene, name: &str) -> std::io::Result<()> {
+    let serialized = serde_json::to_string(&scene)?;
+
+    save_file(&serialized, name)?;
+
+    Ok(())
+}
+
+pub fn read_scene(name: &str) -> std::io::Result<Scene> {
     let serialized = read_file(name)?;
 
     let deserialized = serde_json::from_str(&serialized)?;
 
     Ok(deserialized)
-}
\ No newline at end of file
+}
+
+// Reads a PNG image, the name is relative to saved/ like the other files
+pub fn read_image(name: &str) -> std::io::Result<Image> {
+    let mut path = String::from("saved/");
+    path.push_str(name);
+
+    if !path.contains(".png") {
+        path.push_str(".png");
+    }
+
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // Palettes and low bit depths are expanded and 16 bit channels are stripped, so every channel is a single byte
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+
+    let channels = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()].chunks_exact(channels).map(|pixel| {
+        let channel = |index: usize| pixel[index] as f32 / 255.0;
+
+        match info.color_type {
+            png::ColorType::Grayscale => [channel(0), channel(0), channel(0), 1.0],
+            png::ColorType::GrayscaleAlpha => [channel(0), channel(0), channel(0), channel(1)],
+            png::ColorType::Rgba => [channel(0), channel(1), channel(2), channel(3)],
+            png::ColorType::Rgb | png::ColorType::Indexed => [channel(0), channel(1), channel(2), 1.0],
+        }
+    }).collect();
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
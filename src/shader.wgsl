@@ -20,6 +20,7 @@ struct VertexInput {
 struct InstanceInput {
     @location(1) position: vec2<f32>,
     @location(2) color_id: u32,
+    @location(3) radius_scale: f32,
 };
 
 struct VertexOutput {
@@ -33,7 +34,7 @@ struct VertexOutput {
 fn vs_main(vert: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
-    out.fpos = pc.proj_view * vec4<f32>(vert.vpos * pc.particle_radius * pc.bloom + instance.position, 0.0, 1.0);
+    out.fpos = pc.proj_view * vec4<f32>(vert.vpos * pc.particle_radius * instance.radius_scale * pc.bloom + instance.position, 0.0, 1.0);
     out.fuv = vert.vpos*2.0;
     out.fcolor = colors.colors[instance.color_id].xyz;
 
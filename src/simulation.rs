@@ -0,0 +1,523 @@
+use std::sync::{Arc, atomic::{AtomicPtr, Ordering}, mpsc::{self, Receiver, Sender, TryRecvError}};
+use std::time::{Duration, Instant};
+
+use particle_life::{World, WorldSettings, ParticleSettings, ColorTable, Scene, Interaction, Image, StepStats};
+
+use crate::renderer::MAX_INSTANCES;
+
+// Everything the render thread can ask the simulation thread to do, applied in the order they were sent
+pub enum Command {
+    WorldSettings(WorldSettings),
+    ParticleSettings(ParticleSettings),
+    ColorTable(ColorTable),
+    Scene(Scene),
+    Simulate(bool),
+    Interaction(Option<Interaction>),
+    NewPartitions,
+    ClampColors,
+    Respawn,
+    SetParticleCount(usize),
+    SpawnInCircle { center: glm::Vec2, radius: f32, count: usize, color_id: Option<u8> },
+    EraseInCircle { center: glm::Vec2, radius: f32, color_id: Option<u8> },
+    SpawnImage { image: Image, count: usize, replace: bool },
+    // Finds the particle closest to the position, the result comes back with the snapshots
+    Pick(glm::Vec2),
+}
+
+// Shown in the metrics window
+#[derive(Clone, Debug)]
+pub struct SimulationStats {
+    pub velocity_update_time: f32,
+    pub position_update_time: f32,
+    pub partition_update_time: f32,
+    pub step_stats: StepStats,
+    pub tick: u64,
+    pub ticks_per_second: f32,
+    // The live count, `WorldSettings::max_particles` is only the count last asked for
+    pub particle_count: usize,
+    pub populations: Vec<usize>,
+    pub momentum: glm::Vec2,
+    pub temperature: f32,
+    pub target_temperature: Option<f32>,
+}
+
+// The world after a tick, everything the render thread needs to draw it
+pub struct Snapshot {
+    pub positions: Vec<glm::Vec2>,
+    pub color_ids: Vec<u8>,
+    // The area of a particle grows with its mass
+    pub radius_scales: Vec<f32>,
+    pub ids: Vec<usize>,
+    pub stats: SimulationStats,
+    // Result of the last `Command::Pick`
+    pub picked_id: Option<usize>,
+    // Number of commands applied before it was taken
+    processed_commands: u64,
+    taken: Instant,
+}
+
+impl Snapshot {
+    fn new() -> Self {
+        Self {
+            positions: Vec::new(),
+            color_ids: Vec::new(),
+            radius_scales: Vec::new(),
+            ids: Vec::new(),
+            stats: SimulationStats {
+                velocity_update_time: 0.0,
+                position_update_time: 0.0,
+                partition_update_time: 0.0,
+                step_stats: StepStats::default(),
+                tick: 0,
+                ticks_per_second: 0.0,
+                particle_count: 0,
+                populations: Vec::new(),
+                momentum: glm::Vec2::zeros(),
+                temperature: 0.0,
+                target_temperature: None,
+            },
+            picked_id: None,
+            processed_commands: 0,
+            taken: Instant::now(),
+        }
+    }
+
+    // Reuses the buffers of an older snapshot
+    fn capture(&mut self, world: &World, world_settings: &WorldSettings, color_table: &ColorTable, picked_id: Option<usize>, processed_commands: u64, ticks_per_second: f32) {
+        let count = world.get_particle_count();
+
+        self.positions.clear();
+        self.positions.extend_from_slice(world.get_particle_positions());
+        self.color_ids.clear();
+        self.color_ids.extend_from_slice(world.get_particle_color_ids());
+        self.ids.clear();
+        self.ids.extend_from_slice(world.get_particle_ids());
+        self.radius_scales.clear();
+        self.radius_scales.extend((0..count).map(|index| world.get_particle_mass(index, color_table).sqrt()));
+
+        let thermal = &world_settings.thermal;
+
+        self.stats = SimulationStats {
+            velocity_update_time: world.velocity_update_time,
+            position_update_time: world.position_update_time,
+            partition_update_time: world.partition_update_time,
+            step_stats: world.step_stats,
+            tick: world.get_tick(),
+            ticks_per_second,
+            particle_count: count,
+            populations: world.get_color_populations(color_table),
+            momentum: world.get_total_momentum(color_table),
+            temperature: world.get_temperature(color_table),
+            target_temperature: thermal.is_enabled().then(|| thermal.temperature_at(world.get_time())),
+        };
+
+        self.picked_id = picked_id;
+        self.processed_commands = processed_commands;
+        self.taken = Instant::now();
+    }
+}
+
+// Lock-free hand-off of the snapshots. The newest one waits in `latest` until the render thread takes it,
+// and the render thread gives the ones it's done with back through `spare`, so the buffers get reused
+struct SnapshotBuffer {
+    latest: AtomicPtr<Snapshot>,
+    spare: AtomicPtr<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    fn new() -> Self {
+        Self {
+            latest: AtomicPtr::new(std::ptr::null_mut()),
+            spare: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    // Both slots only ever hold pointers from `Box::into_raw`, and swapping hands their ownership over to the caller
+    fn swap(slot: &AtomicPtr<Snapshot>, snapshot: Option<Box<Snapshot>>) -> Option<Box<Snapshot>> {
+        let new = snapshot.map_or(std::ptr::null_mut(), Box::into_raw);
+        let old = slot.swap(new, Ordering::AcqRel);
+
+        if old.is_null() {
+            None
+        } else {
+            Some(unsafe { Box::from_raw(old) })
+        }
+    }
+
+    // Returns the previous snapshot if it wasn't taken in time
+    fn publish(&self, snapshot: Box<Snapshot>) -> Option<Box<Snapshot>> {
+        Self::swap(&self.latest, Some(snapshot))
+    }
+    fn take(&self) -> Option<Box<Snapshot>> {
+        Self::swap(&self.latest, None)
+    }
+
+    fn give_back(&self, snapshot: Box<Snapshot>) {
+        Self::swap(&self.spare, Some(snapshot));
+    }
+    fn take_spare(&self) -> Option<Box<Snapshot>> {
+        Self::swap(&self.spare, None)
+    }
+}
+
+impl Drop for SnapshotBuffer {
+    fn drop(&mut self) {
+        self.take();
+        self.take_spare();
+    }
+}
+
+struct SimulationThread {
+    world: World,
+    world_settings: WorldSettings,
+    particle_settings: ParticleSettings,
+    color_table: ColorTable,
+    simulate: bool,
+    picked_id: Option<usize>,
+
+    commands: Receiver<Command>,
+    processed_commands: u64,
+    buffer: Arc<SnapshotBuffer>,
+    // Filled with the next snapshot
+    back: Box<Snapshot>,
+}
+
+impl SimulationThread {
+    // Runs until the render thread drops its `Simulation`
+    fn run(mut self) {
+        // None right after starting or resuming, when there's no tick to measure the time since
+        let mut last_tick: Option<Instant> = None;
+        let mut next_tick = Instant::now();
+        let mut ticks_per_second = 0.0;
+
+        loop {
+            let was_paused = !self.simulate;
+
+            // A paused simulation only wakes up for commands
+            if was_paused {
+                match self.commands.recv() {
+                    Ok(command) => self.apply(command),
+                    Err(_) => return,
+                }
+            }
+
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.apply(command),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            if was_paused {
+                last_tick = None;
+                next_tick = Instant::now();
+            }
+
+            if self.simulate {
+                let now = Instant::now();
+                let elapsed = last_tick.map(|last_tick| (now - last_tick).as_secs_f32());
+
+                last_tick = Some(now);
+
+                let time_step = match elapsed {
+                    Some(elapsed) if !self.world_settings.is_time_step_fixed() => elapsed,
+                    _ => self.world_settings.time_step,
+                };
+
+                // Presets may allow more births than can be drawn
+                self.world_settings.ecosystem.max_population = self.world_settings.ecosystem.max_population.min(MAX_INSTANCES - 1);
+
+                self.world.step(time_step, &self.particle_settings, &self.world_settings, &self.color_table);
+
+                if let Some(elapsed) = elapsed.filter(|&elapsed| elapsed > 0.0) {
+                    ticks_per_second = if ticks_per_second == 0.0 { 1.0 / elapsed } else { ticks_per_second * 0.9 + 0.1 / elapsed };
+                }
+            } else {
+                ticks_per_second = 0.0;
+            }
+
+            self.back.capture(&self.world, &self.world_settings, &self.color_table, self.picked_id, self.processed_commands, ticks_per_second);
+
+            let next = self.buffer.take_spare().unwrap_or_else(|| Box::new(Snapshot::new()));
+            let snapshot = std::mem::replace(&mut self.back, next);
+
+            if let Some(unread) = self.buffer.publish(snapshot) {
+                self.back = unread;
+            }
+
+            // Falling behind skips the missed ticks instead of trying to catch up
+            if self.simulate && self.world_settings.tick_rate > 0.0 {
+                next_tick += Duration::from_secs_f32(1.0 / self.world_settings.tick_rate);
+
+                let now = Instant::now();
+
+                if next_tick > now {
+                    std::thread::sleep(next_tick - now);
+                } else {
+                    next_tick = now;
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, command: Command) {
+        let free = MAX_INSTANCES - 1 - self.world.get_particle_count().min(MAX_INSTANCES - 1);
+
+        match command {
+            Command::WorldSettings(world_settings) => {
+                self.world_settings = world_settings;
+                self.world.settings_changed();
+            }
+            Command::ParticleSettings(particle_settings) => {
+                self.particle_settings = particle_settings;
+                self.world.settings_changed();
+            }
+            Command::ColorTable(color_table) => {
+                self.color_table = color_table;
+                self.world.settings_changed();
+            }
+            Command::Scene(scene) => self.world.set_scene(scene),
+            Command::Simulate(simulate) => self.simulate = simulate,
+            Command::Interaction(interaction) => self.world.set_interaction(interaction),
+            Command::NewPartitions => self.world.new_partitions(&self.world_settings, &self.particle_settings, &self.color_table),
+            Command::ClampColors => self.world.clamp_particle_colors(&self.color_table),
+            Command::Respawn => self.world.new_particles(&self.world_settings, &self.color_table),
+            Command::SetParticleCount(count) => self.world.set_particle_count(count, &self.color_table),
+            Command::SpawnInCircle { center, radius, count, color_id } => {
+                self.world.add_particles_in_circle(&center, radius, count.min(free), color_id, &self.color_table);
+            }
+            Command::EraseInCircle { center, radius, color_id } => {
+                let indices: Vec<usize> = self.world.query_radius(&center, radius, &self.world_settings).into_iter().filter(|&index| {
+                    color_id.is_none_or(|color_id| color_id == self.world.get_particle_color_id(index))
+                }).collect();
+
+                self.world.remove_particles_at(&indices);
+            }
+            Command::SpawnImage { image, count, replace } => {
+                if replace {
+                    self.world.remove_particles(|_, _| true);
+                }
+
+                let free = (MAX_INSTANCES - 1).saturating_sub(self.world.get_particle_count());
+
+                self.world.add_particles_from_image(&image, count.min(free), &self.color_table);
+            }
+            Command::Pick(position) => self.picked_id = self.world.get_closest_particle_id(&position, &self.world_settings),
+        }
+
+        self.processed_commands += 1;
+    }
+}
+
+// The render thread's side of the simulation thread
+pub struct Simulation {
+    commands: Sender<Command>,
+    sent_commands: u64,
+    buffer: Arc<SnapshotBuffer>,
+    // Number of commands sent up to the last `Command::Pick` that hasn't been answered yet
+    pick_command: Option<u64>,
+
+    // What the simulation thread was last sent
+    world_settings: WorldSettings,
+    particle_settings: ParticleSettings,
+    color_table: ColorTable,
+    scene: Scene,
+    simulate: bool,
+    interaction: Option<Interaction>,
+
+    previous: Box<Snapshot>,
+    current: Box<Snapshot>,
+    // Index of every id in the snapshots, `usize::MAX` if it's not in there
+    previous_indices: Vec<usize>,
+    current_indices: Vec<usize>,
+    received: Instant,
+
+    positions: Vec<glm::Vec2>,
+}
+
+impl Simulation {
+    pub fn spawn(world: World, world_settings: &WorldSettings, particle_settings: &ParticleSettings, color_table: &ColorTable, simulate: bool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let buffer = Arc::new(SnapshotBuffer::new());
+
+        let mut current = Box::new(Snapshot::new());
+        current.capture(&world, world_settings, color_table, None, 0, 0.0);
+
+        let scene = world.get_scene().clone();
+
+        let thread = SimulationThread {
+            world,
+            world_settings: world_settings.clone(),
+            particle_settings: particle_settings.clone(),
+            color_table: color_table.clone(),
+            simulate,
+            picked_id: None,
+
+            commands: receiver,
+            processed_commands: 0,
+            buffer: buffer.clone(),
+            back: Box::new(Snapshot::new()),
+        };
+
+        std::thread::Builder::new()
+            .name(String::from("simulation"))
+            .spawn(move || thread.run())
+            .unwrap();
+
+        let mut simulation = Self {
+            commands: sender,
+            sent_commands: 0,
+            buffer,
+            pick_command: None,
+
+            world_settings: world_settings.clone(),
+            particle_settings: particle_settings.clone(),
+            color_table: color_table.clone(),
+            scene,
+            simulate,
+            interaction: None,
+
+            previous: Box::new(Snapshot::new()),
+            current,
+            previous_indices: Vec::new(),
+            current_indices: Vec::new(),
+            received: Instant::now(),
+
+            positions: Vec::new(),
+        };
+
+        simulation.current_indices = Self::index_ids(&simulation.current.ids, Vec::new());
+        simulation.positions = simulation.current.positions.clone();
+
+        simulation
+    }
+
+    pub fn send(&mut self, command: Command) {
+        // The thread only stops after the `Simulation` is dropped
+        self.commands.send(command).unwrap();
+        self.sent_commands += 1;
+    }
+
+    // Sends whatever changed since the last call
+    pub fn sync(&mut self, world_settings: &WorldSettings, particle_settings: &ParticleSettings, color_table: &ColorTable, scene: &Scene, simulate: bool, interaction: Option<Interaction>) {
+        if *world_settings != self.world_settings {
+            self.world_settings = world_settings.clone();
+            self.send(Command::WorldSettings(world_settings.clone()));
+        }
+        if *particle_settings != self.particle_settings {
+            self.particle_settings = particle_settings.clone();
+            self.send(Command::ParticleSettings(particle_settings.clone()));
+        }
+        if *color_table != self.color_table {
+            self.color_table = color_table.clone();
+            self.send(Command::ColorTable(color_table.clone()));
+        }
+        if *scene != self.scene {
+            self.scene = scene.clone();
+            self.send(Command::Scene(scene.clone()));
+        }
+        if simulate != self.simulate {
+            self.simulate = simulate;
+            self.send(Command::Simulate(simulate));
+        }
+        if interaction != self.interaction {
+            self.interaction = interaction;
+            self.send(Command::Interaction(interaction));
+        }
+    }
+
+    // Takes the newest snapshot, returns whether there was one
+    pub fn poll(&mut self) -> bool {
+        let Some(snapshot) = self.buffer.take() else {
+            return false;
+        };
+
+        let previous = std::mem::replace(&mut self.previous, std::mem::replace(&mut self.current, snapshot));
+        self.buffer.give_back(previous);
+
+        let indices = std::mem::take(&mut self.previous_indices);
+        self.previous_indices = std::mem::take(&mut self.current_indices);
+        self.current_indices = Self::index_ids(&self.current.ids, indices);
+
+        self.received = Instant::now();
+
+        true
+    }
+
+    fn index_ids(ids: &[usize], mut indices: Vec<usize>) -> Vec<usize> {
+        indices.clear();
+        indices.resize(ids.iter().max().map_or(0, |&max_id| max_id + 1), usize::MAX);
+
+        for (index, &id) in ids.iter().enumerate() {
+            indices[id] = index;
+        }
+
+        indices
+    }
+
+    // Moves the particles between the last two snapshots, by how long ago the last one arrived compared to how long the tick before it took.
+    // The particles are matched by their ids, since their indices change every tick. Particles that jumped further than half the world,
+    // e.g. by wrapping around, are shown where they are now
+    pub fn interpolate(&mut self, half_size: glm::Vec2) {
+        let tick_duration = self.current.taken.saturating_duration_since(self.previous.taken).as_secs_f32();
+
+        let t = if tick_duration > 0.0 {
+            (self.received.elapsed().as_secs_f32() / tick_duration).min(1.0)
+        } else {
+            1.0
+        };
+
+        let (previous, current) = (&self.previous, &self.current);
+        let previous_indices = &self.previous_indices;
+
+        self.positions.clear();
+        self.positions.extend(current.positions.iter().zip(&current.ids).map(|(&position, &id)| {
+            match previous_indices.get(id) {
+                Some(&index) if index != usize::MAX => {
+                    let previous_position = previous.positions[index];
+                    let jump = position - previous_position;
+
+                    if jump.x.abs() > half_size.x || jump.y.abs() > half_size.y {
+                        position
+                    } else {
+                        previous_position + jump * t
+                    }
+                }
+                _ => position,
+            }
+        }));
+    }
+
+    // Interpolated positions, in the order of the current snapshot
+    pub fn positions(&self) -> &[glm::Vec2] {
+        &self.positions
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.current
+    }
+
+    pub fn get_particle_index(&self, id: usize) -> Option<usize> {
+        self.current_indices.get(id).copied().filter(|&index| index != usize::MAX)
+    }
+
+    // Asks for the id of the particle closest to the position, see `take_picked_id`
+    pub fn pick(&mut self, position: &glm::Vec2) {
+        self.send(Command::Pick(*position));
+        self.pick_command = Some(self.sent_commands);
+    }
+
+    // The result of the last `pick` once it's in, None while it's still on its way
+    pub fn take_picked_id(&mut self) -> Option<Option<usize>> {
+        let pick_command = self.pick_command?;
+
+        if self.current.processed_commands < pick_command {
+            return None;
+        }
+
+        self.pick_command = None;
+
+        Some(self.current.picked_id)
+    }
+}
@@ -0,0 +1,165 @@
+use std::f32::consts::TAU;
+use serde::{Serialize, Deserialize};
+use rand::{distributions::{Distribution, WeightedIndex}, rngs::StdRng, Rng};
+
+// How `World::new_particles` places the particles and picks their colors
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpawnPattern {
+    Uniform,
+    // Gaussian blobs around random centers, `spread` is the standard deviation
+    Clusters { count: usize, spread: f32 },
+    // Every color gets its own ring around the center
+    Rings { width: f32 },
+    // Every color gets its own stripe
+    Stripes { vertical: bool },
+    // `radius` is relative to the shorter side of the world
+    Disk { radius: f32 },
+    // Every color gets its own block of the world
+    Segregated,
+    // `jitter` is relative to the lattice spacing
+    Lattice { jitter: f32 },
+}
+
+impl SpawnPattern {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpawnPattern::Uniform => "Uniform",
+            SpawnPattern::Clusters { .. } => "Clusters",
+            SpawnPattern::Rings { .. } => "Rings",
+            SpawnPattern::Stripes { .. } => "Stripes",
+            SpawnPattern::Disk { .. } => "Disk",
+            SpawnPattern::Segregated => "Segregated",
+            SpawnPattern::Lattice { .. } => "Lattice",
+        }
+    }
+
+    pub fn all() -> [SpawnPattern; 7] {
+        [
+            SpawnPattern::Uniform,
+            SpawnPattern::Clusters { count: 8, spread: 200.0 },
+            SpawnPattern::Rings { width: 100.0 },
+            SpawnPattern::Stripes { vertical: true },
+            SpawnPattern::Disk { radius: 0.5 },
+            SpawnPattern::Segregated,
+            SpawnPattern::Lattice { jitter: 0.2 },
+        ]
+    }
+
+    // The positions of these patterns depend on the color, so the colors have to be picked first
+    fn depends_on_color(&self) -> bool {
+        matches!(self, SpawnPattern::Rings { .. } | SpawnPattern::Stripes { .. } | SpawnPattern::Segregated)
+    }
+
+    // Positions within `-half_size..=half_size` and colors of `count` new particles.
+    // Colors are picked proportionally to `ratios`, missing entries count as 1 and all of them being 0 picks uniformly
+    pub fn generate(&self, count: usize, color_count: usize, ratios: &[f32], half_size: glm::Vec2, r: &mut StdRng) -> (Vec<glm::Vec2>, Vec<u8>) {
+        let weights: Vec<f32> = (0..color_count).map(|color_id| ratios.get(color_id).copied().unwrap_or(1.0).max(0.0)).collect();
+        let weighted = if ratios.is_empty() { None } else { WeightedIndex::new(&weights).ok() };
+
+        let gen_colors = |r: &mut StdRng| -> Vec<u8> {
+            (0..count).map(|_| match &weighted {
+                Some(weighted) => weighted.sample(r) as u8,
+                None => r.gen_range(0..color_count as u8),
+            }).collect()
+        };
+
+        let cluster_centers: Vec<glm::Vec2> = match self {
+            SpawnPattern::Clusters { count, .. } => (0..(*count).max(1)).map(|_| Self::uniform(half_size, r)).collect(),
+            _ => Vec::new(),
+        };
+
+        let (positions, color_ids): (Vec<glm::Vec2>, Vec<u8>) = if self.depends_on_color() {
+            let color_ids = gen_colors(r);
+            let positions = color_ids.iter().enumerate().map(|(index, &color_id)| {
+                self.position(index, count, color_id as usize, color_count, half_size, &cluster_centers, r)
+            }).collect();
+
+            (positions, color_ids)
+        } else {
+            let positions = (0..count).map(|index| {
+                self.position(index, count, 0, color_count, half_size, &cluster_centers, r)
+            }).collect();
+
+            (positions, gen_colors(r))
+        };
+
+        let positions = positions.iter().map(|position| glm::clamp_vec(position, &-half_size, &half_size)).collect();
+
+        (positions, color_ids)
+    }
+
+    fn uniform(half_size: glm::Vec2, r: &mut StdRng) -> glm::Vec2 {
+        glm::Vec2::new(
+            r.gen_range(-half_size.x..=half_size.x),
+            r.gen_range(-half_size.y..=half_size.y)
+        )
+    }
+
+    // Box-Muller transform, returns two independent samples of the standard normal distribution
+    fn gaussian(r: &mut StdRng) -> glm::Vec2 {
+        let (u1, u2): (f32, f32) = (1.0 - r.gen::<f32>(), r.gen());
+        let length = (-2.0 * u1.ln()).sqrt();
+
+        glm::Vec2::new((TAU * u2).cos(), (TAU * u2).sin()) * length
+    }
+
+    // Block `index` out of `block_count` blocks covering the world, as its min and max corners
+    fn block(index: usize, block_count: usize, half_size: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+        let columns = (block_count as f32).sqrt().ceil().max(1.0) as usize;
+        let rows = block_count.div_ceil(columns);
+
+        let block_size = glm::Vec2::new(half_size.x * 2.0 / columns as f32, half_size.y * 2.0 / rows.max(1) as f32);
+        let min = -half_size + glm::Vec2::new((index % columns) as f32, (index / columns) as f32).component_mul(&block_size);
+
+        (min, min + block_size)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn position(&self, index: usize, count: usize, color_id: usize, color_count: usize, half_size: glm::Vec2, cluster_centers: &[glm::Vec2], r: &mut StdRng) -> glm::Vec2 {
+        match *self {
+            SpawnPattern::Uniform => Self::uniform(half_size, r),
+            SpawnPattern::Clusters { spread, .. } => {
+                let center = cluster_centers[r.gen_range(0..cluster_centers.len())];
+
+                center + Self::gaussian(r) * spread
+            }
+            SpawnPattern::Rings { width } => {
+                let radius = half_size.x.min(half_size.y) * (color_id as f32 + 0.5) / color_count as f32 + r.gen_range(-0.5..=0.5) * width;
+                let angle = r.gen_range(0.0..TAU);
+
+                glm::Vec2::new(angle.cos(), angle.sin()) * radius
+            }
+            SpawnPattern::Stripes { vertical } => {
+                let (axis, other_axis) = if vertical { (half_size.x, half_size.y) } else { (half_size.y, half_size.x) };
+
+                let stripe_size = axis * 2.0 / color_count as f32;
+                let along = -axis + stripe_size * (color_id as f32 + r.gen_range(0.0..=1.0));
+                let across = r.gen_range(-other_axis..=other_axis);
+
+                if vertical { glm::Vec2::new(along, across) } else { glm::Vec2::new(across, along) }
+            }
+            SpawnPattern::Disk { radius } => {
+                let radius = half_size.x.min(half_size.y) * radius * r.gen_range(0.0f32..=1.0).sqrt();
+                let angle = r.gen_range(0.0..TAU);
+
+                glm::Vec2::new(angle.cos(), angle.sin()) * radius
+            }
+            SpawnPattern::Segregated => {
+                let (min, max) = Self::block(color_id, color_count, half_size);
+
+                glm::Vec2::new(r.gen_range(min.x..=max.x), r.gen_range(min.y..=max.y))
+            }
+            SpawnPattern::Lattice { jitter } => {
+                // Lattice cells as close to squares as the world's aspect ratio allows
+                let columns = ((count as f32 * half_size.x / half_size.y).sqrt().ceil() as usize).max(1);
+                let rows = count.div_ceil(columns).max(1);
+
+                let cell_size = glm::Vec2::new(half_size.x * 2.0 / columns as f32, half_size.y * 2.0 / rows as f32);
+                let cell = glm::Vec2::new((index % columns) as f32 + 0.5, (index / columns) as f32 + 0.5);
+                let offset = glm::Vec2::new(r.gen_range(-0.5..=0.5), r.gen_range(-0.5..=0.5)) * jitter;
+
+                -half_size + (cell + offset).component_mul(&cell_size)
+            }
+        }
+    }
+}
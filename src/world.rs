@@ -1,74 +1,207 @@
-use std::{hash::{Hash, Hasher}, collections::hash_map::DefaultHasher};
-use rayon::prelude::{ParallelIterator, IndexedParallelIterator, IntoParallelRefIterator};
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::hash::{Hash, Hasher};
+use rayon::prelude::{ParallelIterator, IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, IndexedParallelIterator};
+use rand::{distributions::{Distribution, WeightedIndex}, rngs::StdRng, Rng, SeedableRng};
 use crate::{
-    particle_settings::ParticleSettings,
-    world_settings::{WorldSettings, ParticleWrapping},
-    color_table::ColorTable
+    particle_settings::{ParticleSettings, Integrator},
+    force_kernel::{ForceKernel, Kernel, Lanes, LANES},
+    world_settings::{WorldSettings, ParticleWrapping, Thermostat},
+    color_table::ColorTable,
+    stable_hasher::StableHasher,
+    obstacle::Scene,
+    force_field::Interaction,
+    image::Image
 };
 
-const DEFAULT_NUM_PARTICLES_PER_CELL: usize = 256;
 const BARRIER_MARGIN: f32 = 0.1;
+// Per second, the Brownian noise kicks at least as hard as if there was this much drag
+const MIN_NOISE_FRICTION: f32 = 1.0;
 
-#[derive(Clone, Default, Debug, PartialEq, Eq)]
-pub struct PartitionCell {
-    pub particles: Vec<usize>,
+// Range of `World::partition_particles` holding the indices of the particles inside the partition
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+struct PartitionCell {
+    start: usize,
+    end: usize,
 }
 
-impl PartitionCell {
-    pub fn new() -> Self {
-        Self {
-            particles: Vec::with_capacity(DEFAULT_NUM_PARTICLES_PER_CELL),
-        }
-    }
+// A partition next to another one, particles inside it appear at `position.component_mul(&mirror) + offset`
+// when seen from the other partition. The mirror is only used by the non-orientable topologies
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PartitionNeighbor {
+    partition: usize,
+    mirror: glm::Vec2,
+    offset: glm::Vec2,
+}
+
+// Uniform random number in [0, 1) that only depends on its inputs, so it can be used from any thread 
+// without affecting the outcome. Based on splitmix64
+fn hash_random(seed: u64, tick: u64, index: usize, stream: usize) -> f32 {
+    let mut x = seed ^ tick.wrapping_mul(0x9E3779B97F4A7C15) ^ (index as u64).wrapping_mul(0xBF58476D1CE4E5B9) ^ (stream as u64).wrapping_mul(0x94D049BB133111EB);
+
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^= x >> 31;
+
+    (x >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// Color table entry and radii of every pair with a particle of one color. 
+// Every possible color id has an entry, so looking up the lanes' parameters never needs a bounds check
+struct PairRow {
+    attraction: [f32; 256],
+    min_r: [f32; 256],
+    max_r: [f32; 256],
+}
+
+// What the last `World::step` did, the times are in seconds
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StepStats {
+    pub substeps: usize,
+    pub substep_size: f32,
+    // Left over once `max_substeps` was reached
+    pub dropped_time: f32,
+    pub max_speed: f32,
+    pub capped_particles: usize,
 }
 
 pub struct World {
     particle_positions: Vec<glm::Vec2>,
     particle_velocities: Vec<glm::Vec2>,
     particle_color_ids: Vec<u8>,
+    // Multiplies the mass of the particle's color, 1 unless set otherwise
+    particle_mass_factors: Vec<f32>,
+    // Only changes while the ecosystem is enabled
+    particle_energies: Vec<f32>,
+    // Stable across reorders, unlike the indices
+    particle_ids: Vec<usize>,
+    next_particle_id: usize,
+    // Current index of every id handed out so far, `usize::MAX` once the particle is removed
+    particle_indices: Vec<usize>,
+    particle_partition_ids: Vec<usize>,
     partitions: Vec<PartitionCell>,
+    // Particle indices sorted by partition, and by id within every partition
+    partition_particles: Vec<usize>,
+    // Order of the partitions in `partition_particles` and in memory after reordering, row by row or along a Morton curve
+    partition_order: Vec<usize>,
+    partition_neighbors: Vec<Vec<PartitionNeighbor>>,
+    // Only filled in the Newtonian mode, every pair of neighboring partitions is in here once
+    partition_half_neighbors: Vec<Vec<PartitionNeighbor>>,
+
+    particle_accelerations: Vec<glm::Vec2>,
+    // Value of `force_generation` when the accelerations were computed, they're only reused while it stays the same
+    particle_accelerations_generation: u64,
+    // Bumped whenever anything `apply_forces` reads besides the particles changes
+    force_generation: u64,
+
+    // Indexed by color, only rebuilt when the color table or the global radii change
+    pair_rows: Vec<PairRow>,
+    pair_rows_inputs: Option<(ColorTable, f32, f32)>,
 
-    size: f32,
-    half_size: f32,
-    cell_size: f32, 
-    cell_count: usize,
+    size: glm::Vec2,
+    half_size: glm::Vec2,
+    cell_size: glm::Vec2, 
+    cell_count_x: usize,
+    cell_count_y: usize,
+
+    tick: u64,
+    // Simulated seconds since the particles were spawned
+    time: f32,
+
+    // Used for particles spawned at runtime, reseeded with every respawn
+    spawn_rng: StdRng,
+
+    scene: Scene,
+    // Applied every tick while set, cleared by the owner
+    interaction: Option<Interaction>,
 
     pub velocity_update_time: f32,
     pub position_update_time: f32,
     pub partition_update_time: f32,
+
+    pub step_stats: StepStats,
 }
 
 impl World {
-    pub fn new(world_settings: &WorldSettings, particle_settings: &ParticleSettings) -> Self {
-        let size = world_settings.size;
-        let half_size = size / 2.0;
-        let cell_size = particle_settings.max_r;
-        let cell_count = (size / cell_size).ceil() as usize;
-
-        Self {
+    pub fn new(world_settings: &WorldSettings, particle_settings: &ParticleSettings, color_table: &ColorTable) -> Self {
+        let mut world = Self {
             particle_positions: Vec::new(),
             particle_velocities: Vec::new(),
             particle_color_ids: Vec::new(),
-            partitions: vec![PartitionCell::new(); cell_count*cell_count], 
+            particle_mass_factors: Vec::new(),
+            particle_energies: Vec::new(),
+            particle_ids: Vec::new(),
+            next_particle_id: 0,
+            particle_indices: Vec::new(),
+            particle_partition_ids: Vec::new(),
+            partitions: Vec::new(), 
+            partition_particles: Vec::new(),
+            partition_order: Vec::new(),
+            partition_neighbors: Vec::new(),
+            partition_half_neighbors: Vec::new(),
+
+            particle_accelerations: Vec::new(),
+            particle_accelerations_generation: 0,
+            force_generation: 0,
+
+            pair_rows: Vec::new(),
+            pair_rows_inputs: None,
+
+            size: glm::Vec2::zeros(),
+            half_size: glm::Vec2::zeros(),
+            cell_size: glm::Vec2::zeros(),
+            cell_count_x: 0,
+            cell_count_y: 0,
 
-            size,
-            half_size,
-            cell_size,
-            cell_count,
+            tick: 0,
+            time: 0.0,
+
+            spawn_rng: StdRng::seed_from_u64(world_settings.sub_seed_hash("spawn")),
+
+            scene: Scene::default(),
+            interaction: None,
 
             velocity_update_time: 0.0,
             position_update_time: 0.0,
             partition_update_time: 0.0,
-        }
+
+            step_stats: StepStats::default(),
+        };
+
+        world.new_partitions(world_settings, particle_settings, color_table);
+
+        world
     }
 
-    pub fn get_particle_positions(&self) -> &Vec<glm::Vec2> {
+    pub fn get_particle_positions(&self) -> &[glm::Vec2] {
         &self.particle_positions
     }
-    pub fn get_particle_color_ids(&self) -> &Vec<u8> {
+    pub fn get_particle_velocities(&self) -> &[glm::Vec2] {
+        &self.particle_velocities
+    }
+    pub fn get_particle_color_ids(&self) -> &[u8] {
         &self.particle_color_ids
     }
+    pub fn get_particle_mass_factors(&self) -> &[f32] {
+        &self.particle_mass_factors
+    }
+    pub fn get_particle_energies(&self) -> &[f32] {
+        &self.particle_energies
+    }
+    pub fn get_particle_ids(&self) -> &[usize] {
+        &self.particle_ids
+    }
+
+    // Number of particles of every color
+    pub fn get_color_populations(&self, color_table: &ColorTable) -> Vec<usize> {
+        let mut populations = vec![0; color_table.colors.len()];
+
+        for &color_id in &self.particle_color_ids {
+            if let Some(population) = populations.get_mut(color_id as usize) {
+                *population += 1;
+            }
+        }
+
+        populations
+    }
 
     pub fn get_particle_position(&self, index: usize) -> glm::Vec2 {
         *self.particle_positions.get(index).unwrap()
@@ -76,27 +209,335 @@ impl World {
     pub fn get_particle_color_id(&self, index: usize) -> u8 {
         *self.particle_color_ids.get(index).unwrap()
     }
+    pub fn get_particle_id(&self, index: usize) -> usize {
+        *self.particle_ids.get(index).unwrap()
+    }
+
+    // Current index of the particle with the given id, None if it was removed. 
+    // Indices change with every `update_partitions`, so particles should be tracked by their ids
+    pub fn get_particle_index(&self, id: usize) -> Option<usize> {
+        self.particle_indices.get(id).copied().filter(|&index| index != usize::MAX)
+    }
+
+    pub fn get_particle_mass(&self, index: usize, color_table: &ColorTable) -> f32 {
+        color_table.masses[self.particle_color_ids[index] as usize] * self.particle_mass_factors[index]
+    }
+
+    pub fn set_particle_mass_factor(&mut self, index: usize, mass_factor: f32) {
+        self.particle_mass_factors[index] = mass_factor;
+        self.particle_accelerations.clear();
+    }
+    pub fn set_particle_velocity(&mut self, index: usize, velocity: glm::Vec2) {
+        self.particle_velocities[index] = velocity;
+    }
+
+    pub fn get_particle_count(&self) -> usize {
+        self.particle_positions.len()
+    }
+
+    // Sum of every particle's mass times its velocity
+    pub fn get_total_momentum(&self, color_table: &ColorTable) -> glm::Vec2 {
+        (0..self.particle_velocities.len()).fold(glm::Vec2::zeros(), |momentum, index| {
+            momentum + self.particle_velocities[index] * self.get_particle_mass(index, color_table)
+        })
+    }
+
+    // Mean kinetic energy of the particles, see `ThermalSettings`
+    pub fn get_temperature(&self, color_table: &ColorTable) -> f32 {
+        if self.particle_velocities.is_empty() {
+            return 0.0;
+        }
+
+        let kinetic_energy = (0..self.particle_velocities.len()).fold(0.0, |kinetic_energy, index| {
+            kinetic_energy + glm::length2(&self.particle_velocities[index]) * self.get_particle_mass(index, color_table) * 0.5
+        });
+
+        kinetic_energy / self.particle_velocities.len() as f32
+    }
+
+    pub fn get_scene(&self) -> &Scene {
+        &self.scene
+    }
+    pub fn set_scene(&mut self, scene: Scene) {
+        self.scene = scene;
+        self.force_generation += 1;
+    }
+
+    pub fn get_interaction(&self) -> Option<Interaction> {
+        self.interaction
+    }
+    pub fn set_interaction(&mut self, interaction: Option<Interaction>) {
+        self.interaction = interaction;
+        self.force_generation += 1;
+    }
+
+    // Has to be called whenever the settings or the color table passed to `step` change, 
+    // otherwise Velocity Verlet reuses accelerations computed with the old ones for one more tick
+    pub fn settings_changed(&mut self) {
+        self.force_generation += 1;
+    }
+
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    // Hashes the particles in the order of their ids, so the hash doesn't depend on how they're laid out in memory
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+
+        let mut order: Vec<usize> = (0..self.particle_ids.len()).collect();
+        order.sort_unstable_by_key(|&index| self.particle_ids[index]);
+
+        for &index in &order {
+            let (position, velocity) = (self.particle_positions[index], self.particle_velocities[index]);
+
+            position.x.to_bits().hash(&mut hasher);
+            position.y.to_bits().hash(&mut hasher);
+            velocity.x.to_bits().hash(&mut hasher);
+            velocity.y.to_bits().hash(&mut hasher);
+        }
+        order.iter().map(|&index| self.particle_color_ids[index]).collect::<Vec<u8>>().hash(&mut hasher);
+
+        hasher.finish()
+    }
 
     pub fn new_particles(&mut self, world_settings: &WorldSettings, color_table: &ColorTable) {
-        let mut hasher = DefaultHasher::new();
-        world_settings.seed.hash(&mut hasher);
-        
-        let seed = hasher.finish();
-
-        let mut r = StdRng::seed_from_u64(seed);
-        
-        self.particle_positions = (0..world_settings.max_particles).map(|_| {
-            glm::Vec2::new(
-                r.gen_range(-self.half_size+BARRIER_MARGIN..=self.half_size-BARRIER_MARGIN),
-                r.gen_range(-self.half_size+BARRIER_MARGIN..=self.half_size-BARRIER_MARGIN)
-            )
-        }).collect();
+        self.tick = 0;
+        self.time = 0.0;
+        self.particle_accelerations.clear();
+
+        let mut r = StdRng::seed_from_u64(world_settings.seed_hash());
+
+        (self.particle_positions, self.particle_color_ids) = world_settings.spawn_pattern.generate(
+            world_settings.max_particles,
+            color_table.colors.len(),
+            &world_settings.species_ratios,
+            self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN),
+            &mut r
+        );
         self.particle_velocities = (0..world_settings.max_particles).map(|_| {
             glm::Vec2::zeros()
         }).collect();
-        self.particle_color_ids = (0..world_settings.max_particles).map(|_| {
-            r.gen_range(0..color_table.colors.len() as u8)
+        self.particle_mass_factors = vec![1.0; world_settings.max_particles];
+        self.particle_energies = vec![1.0; world_settings.max_particles];
+        self.particle_ids = (0..world_settings.max_particles).collect();
+        self.next_particle_id = world_settings.max_particles;
+        self.index_particle_ids();
+
+        self.spawn_rng = StdRng::seed_from_u64(world_settings.sub_seed_hash("spawn"));
+
+        self.rebuild_partitions();
+    }
+
+    // Adds a single resting particle, the position is clamped to the world. Returns its index
+    pub fn add_particle(&mut self, position: &glm::Vec2, color_id: u8) -> usize {
+        let limit = self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN);
+
+        self.push_particle(glm::clamp_vec(position, &-limit, &limit), glm::Vec2::zeros(), color_id, 1.0, 1.0);
+
+        self.particles_changed();
+
+        self.particle_positions.len() - 1
+    }
+
+    // Adds `count` particles uniformly distributed over the circle, with random colors if `color_id` is None
+    pub fn add_particles_in_circle(&mut self, center: &glm::Vec2, radius: f32, count: usize, color_id: Option<u8>, color_table: &ColorTable) {
+        self.add_particles_with(count, color_id, color_table, |r| {
+            let angle = r.gen_range(0.0..std::f32::consts::TAU);
+            let distance = radius * r.gen_range(0.0f32..=1.0).sqrt();
+
+            center + glm::Vec2::new(angle.cos(), angle.sin()) * distance
+        });
+    }
+
+    // Adds `count` particles uniformly distributed over the rectangle, with random colors if `color_id` is None
+    pub fn add_particles_in_rect(&mut self, min: &glm::Vec2, max: &glm::Vec2, count: usize, color_id: Option<u8>, color_table: &ColorTable) {
+        self.add_particles_with(count, color_id, color_table, |r| {
+            glm::Vec2::new(
+                if min.x < max.x { r.gen_range(min.x..max.x) } else { min.x },
+                if min.y < max.y { r.gen_range(min.y..max.y) } else { min.y }
+            )
+        });
+    }
+
+    // Adds up to `count` particles over the image stretched to fit the world, more of them where the image is brighter.
+    // Every particle gets the color closest to its pixel's color. Returns the number of added particles, 0 for dark images
+    pub fn add_particles_from_image(&mut self, image: &Image, count: usize, color_table: &ColorTable) -> usize {
+        let weights = (0..image.height).flat_map(|y| (0..image.width).map(move |x| image.brightness(x, y)));
+
+        let weighted = match WeightedIndex::new(weights) {
+            Ok(weighted) => weighted,
+            Err(_) => return 0,
+        };
+
+        let limit = self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN);
+        let (min, max) = image.fitted_rect(limit);
+        let pixel_size = (max - min).component_div(&glm::Vec2::new(image.width as f32, image.height as f32));
+
+        for _ in 0..count {
+            let pixel = weighted.sample(&mut self.spawn_rng);
+            let (x, y) = (pixel % image.width, pixel / image.width);
+
+            // The first row of the image is at the top of the world
+            let cell = glm::Vec2::new(x as f32, (image.height - 1 - y) as f32);
+            let offset = glm::Vec2::new(self.spawn_rng.gen_range(0.0..1.0), self.spawn_rng.gen_range(0.0..1.0));
+            let position = min + (cell + offset).component_mul(&pixel_size);
+
+            let color_id = image.nearest_color(x, y, &color_table.colors);
+
+            self.push_particle(glm::clamp_vec(&position, &-limit, &limit), glm::Vec2::zeros(), color_id, 1.0, 1.0);
+        }
+
+        self.particles_changed();
+
+        count
+    }
+
+    // Adds or removes particles until there are exactly `count` of them. 
+    // New particles are spread over the whole world, the newest ones are removed first
+    pub fn set_particle_count(&mut self, count: usize, color_table: &ColorTable) {
+        let current = self.particle_positions.len();
+
+        if count > current {
+            let limit = self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN);
+
+            self.add_particles_in_rect(&-limit, &limit, count - current, None, color_table);
+        } else if count < current {
+            let mut ids = self.particle_ids.clone();
+            let first_removed_id = *ids.select_nth_unstable(count).1;
+
+            let keep: Vec<bool> = self.particle_ids.iter().map(|&id| id < first_removed_id).collect();
+
+            self.retain_particles(&keep);
+            self.particles_changed();
+        }
+    }
+
+    // Removes a single particle, the indices of all the following particles shift down by one
+    pub fn remove_particle(&mut self, index: usize) {
+        self.particle_positions.remove(index);
+        self.particle_velocities.remove(index);
+        self.particle_color_ids.remove(index);
+        self.particle_mass_factors.remove(index);
+        self.particle_energies.remove(index);
+        self.particle_ids.remove(index);
+        self.index_particle_ids();
+
+        self.particles_changed();
+    }
+
+    pub fn remove_particles_in_circle(&mut self, center: &glm::Vec2, radius: f32, world_settings: &WorldSettings) -> usize {
+        self.remove_particles_at(&self.query_radius(center, radius, world_settings))
+    }
+
+    pub fn remove_particles_in_rect(&mut self, min: &glm::Vec2, max: &glm::Vec2, world_settings: &WorldSettings) -> usize {
+        self.remove_particles_at(&self.query_rect(min, max, world_settings))
+    }
+
+    // Removes the particles at the given indices, e.g. from a query. Returns how many particles were removed
+    pub fn remove_particles_at(&mut self, indices: &[usize]) -> usize {
+        let mut keep = vec![true; self.particle_positions.len()];
+
+        for &index in indices {
+            keep[index] = false;
+        }
+
+        let removed = self.retain_particles(&keep);
+
+        if removed > 0 {
+            self.particles_changed();
+        }
+
+        removed
+    }
+
+    pub fn remove_particles_with_color(&mut self, color_id: u8) -> usize {
+        self.remove_particles(|_, other_color_id| other_color_id == color_id)
+    }
+
+    // Removes every particle matching the predicate while keeping the order of the remaining ones. 
+    // Returns how many particles were removed
+    pub fn remove_particles(&mut self, predicate: impl Fn(&glm::Vec2, u8) -> bool) -> usize {
+        let keep: Vec<bool> = self.particle_positions.iter().zip(self.particle_color_ids.iter()).map(|(position, &color_id)| {
+            !predicate(position, color_id)
         }).collect();
+
+        let removed = self.retain_particles(&keep);
+
+        if removed > 0 {
+            self.particles_changed();
+        }
+
+        removed
+    }
+
+    // Keeps the particles with a `true` in `keep`, returns how many were removed
+    fn retain_particles(&mut self, keep: &[bool]) -> usize {
+        fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+            let mut keep_iter = keep.iter();
+            values.retain(|_| *keep_iter.next().unwrap());
+        }
+
+        let removed = keep.iter().filter(|&&keep| !keep).count();
+
+        if removed > 0 {
+            retain(&mut self.particle_positions, keep);
+            retain(&mut self.particle_velocities, keep);
+            retain(&mut self.particle_color_ids, keep);
+            retain(&mut self.particle_mass_factors, keep);
+            retain(&mut self.particle_energies, keep);
+            retain(&mut self.particle_ids, keep);
+
+            self.index_particle_ids();
+        }
+
+        removed
+    }
+
+    fn add_particles_with(&mut self, count: usize, color_id: Option<u8>, color_table: &ColorTable, mut position: impl FnMut(&mut StdRng) -> glm::Vec2) {
+        let limit = self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN);
+        let color_count = color_table.colors.len() as u8;
+
+        for _ in 0..count {
+            let new_position = position(&mut self.spawn_rng);
+            let new_color_id = color_id.unwrap_or_else(|| self.spawn_rng.gen_range(0..color_count));
+
+            self.push_particle(glm::clamp_vec(&new_position, &-limit, &limit), glm::Vec2::zeros(), new_color_id.min(color_count - 1), 1.0, 1.0);
+        }
+
+        self.particles_changed();
+    }
+
+    // Appends a particle with a new id, the partitions have to be rebuilt afterwards
+    fn push_particle(&mut self, position: glm::Vec2, velocity: glm::Vec2, color_id: u8, mass_factor: f32, energy: f32) {
+        self.particle_positions.push(position);
+        self.particle_velocities.push(velocity);
+        self.particle_color_ids.push(color_id);
+        self.particle_mass_factors.push(mass_factor);
+        self.particle_energies.push(energy);
+        self.particle_ids.push(self.next_particle_id);
+        self.particle_indices.push(self.particle_ids.len() - 1);
+
+        self.next_particle_id += 1;
+    }
+
+    // Rebuilds `particle_indices` after the particles were removed or moved around
+    fn index_particle_ids(&mut self) {
+        self.particle_indices.clear();
+        self.particle_indices.resize(self.next_particle_id, usize::MAX);
+
+        for (index, &id) in self.particle_ids.iter().enumerate() {
+            self.particle_indices[id] = index;
+        }
+    }
+
+    // Keeps the partitions valid for queries in between ticks and drops the accelerations cached for the old particles
+    fn particles_changed(&mut self) {
+        self.particle_accelerations.clear();
+        self.rebuild_partitions();
     }
 
     pub fn clamp_particle_colors(&mut self, color_table: &ColorTable) {
@@ -105,277 +546,1174 @@ impl World {
         })
     }
 
+    // Advances the simulation by `delta_time`, split into substeps according to the world settings, 
+    // so a long frame takes more substeps instead of a single huge one that would make the particles tunnel or explode
+    pub fn step(&mut self, delta_time: f32, particle_settings: &ParticleSettings, world_settings: &WorldSettings, color_table: &ColorTable) {
+        let max_step = world_settings.max_step.max(0.0001);
+        let min_r = color_table.smallest_min_r(particle_settings.min_r).max(0.0001);
+
+        let (mut velocity_update_time, mut position_update_time, mut partition_update_time) = (0.0, 0.0, 0.0);
+        let mut remaining = delta_time;
+
+        let mut stats = StepStats::default();
+
+        while remaining > 0.0 && stats.substeps < world_settings.max_substeps.max(1) {
+            let mut limit = max_step;
+
+            if world_settings.adaptive_step {
+                let max_speed = self.particle_velocities.iter().fold(0.0, |max_speed: f32, velocity| max_speed.max(glm::length(velocity)));
+
+                if max_speed > 0.0 {
+                    limit = limit.min(world_settings.courant * min_r / max_speed);
+                }
+            }
+
+            // Equal substeps for the rest of the tick, so there's no tiny one at the end. 
+            // The tolerance keeps rounding errors from adding a whole extra substep
+            let step = remaining / (remaining / limit - 0.0001).ceil().max(1.0);
+
+            self.update_partitions();
+            self.update_particles(step, particle_settings, world_settings, color_table);
+
+            if world_settings.velocity_cap {
+                stats.capped_particles += self.cap_velocities(world_settings.max_speed);
+            }
+
+            velocity_update_time += self.velocity_update_time;
+            position_update_time += self.position_update_time;
+            partition_update_time += self.partition_update_time;
+
+            remaining -= step;
+
+            stats.substeps += 1;
+            stats.substep_size = step;
+        }
+
+        self.velocity_update_time = velocity_update_time;
+        self.position_update_time = position_update_time;
+        self.partition_update_time = partition_update_time;
+
+        // Keeps the spatial queries exact until the next step
+        if stats.substeps > 0 {
+            self.rebuild_partitions();
+        }
+
+        stats.dropped_time = remaining.max(0.0);
+        stats.max_speed = self.particle_velocities.iter().fold(0.0, |max_speed: f32, velocity| max_speed.max(glm::length(velocity)));
+
+        self.step_stats = stats;
+    }
+
+    // Slows the particles down to `max_speed`, returns how many were too fast
+    fn cap_velocities(&mut self, max_speed: f32) -> usize {
+        let max_speed = max_speed.max(0.0);
+        let mut capped = 0;
+
+        for velocity in &mut self.particle_velocities {
+            let speed = glm::length(velocity);
+
+            if speed > max_speed {
+                *velocity *= max_speed / speed;
+                capped += 1;
+            }
+        }
+
+        capped
+    }
+
     pub fn update_particles(&mut self, delta_time: f32, particle_settings: &ParticleSettings, world_settings: &WorldSettings, color_table: &ColorTable) {
+        // Dispatching once here lets the compiler specialise the whole force loop for each kernel
+        match particle_settings.kernel {
+            Kernel::Tent(kernel) => self.update_particles_with_kernel(&kernel, delta_time, particle_settings, world_settings, color_table),
+            Kernel::Sine(kernel) => self.update_particles_with_kernel(&kernel, delta_time, particle_settings, world_settings, color_table),
+            Kernel::Gaussian(kernel) => self.update_particles_with_kernel(&kernel, delta_time, particle_settings, world_settings, color_table),
+            Kernel::LennardJones(kernel) => self.update_particles_with_kernel(&kernel, delta_time, particle_settings, world_settings, color_table),
+            Kernel::Smoothstep(kernel) => self.update_particles_with_kernel(&kernel, delta_time, particle_settings, world_settings, color_table),
+        }
+    }
+
+    // Same as `update_particles` but with a custom force kernel instead of the one selected in `particle_settings`
+    pub fn update_particles_with_kernel<K: ForceKernel + Sync>(&mut self, kernel: &K, delta_time: f32, particle_settings: &ParticleSettings, world_settings: &WorldSettings, color_table: &ColorTable) {
         let start = std::time::Instant::now();
 
-        let particle_positions_vec_ptr: *mut Vec<glm::Vec2> = &mut self.particle_positions;
-        let particle_positions_vec_addr = particle_positions_vec_ptr as usize;
+        let mut force_time = 0.0;
 
-        let particle_velocities_vec_ptr: *mut Vec<glm::Vec2> = &mut self.particle_velocities;
-        let particle_velocities_vec_addr = particle_velocities_vec_ptr as usize;
+        let drag = particle_settings.drag.powi(6).powf(delta_time);
 
-        let particle_color_ids_vec_ptr: *mut Vec<u8> = &mut self.particle_color_ids;
-        let particle_color_ids_vec_addr = particle_color_ids_vec_ptr as usize;
+        self.partition_neighbors = (0..self.partitions.len()).map(|index| {
+            self.get_partition_neighbors(index, world_settings.wrapping)
+        }).collect();
+        self.partition_half_neighbors = if particle_settings.newtonian {
+            (0..self.partitions.len()).map(|index| {
+                self.get_half_partition_neighbors(index, world_settings.wrapping)
+            }).collect()
+        } else {
+            Vec::new()
+        };
 
-        let particle_speed = 75.0 * particle_settings.force * delta_time;
+        self.update_pair_rows(particle_settings, color_table);
 
-        let drag = particle_settings.drag.powi(6);
+        if !color_table.transmutations.is_empty() {
+            self.apply_transmutations(delta_time, world_settings, color_table);
+        }
+        if world_settings.ecosystem.enabled {
+            self.apply_ecosystem(delta_time, particle_settings, world_settings, color_table);
+        }
 
-        let min_r_norm = particle_settings.min_r / particle_settings.max_r;
- 
-        // par_iter() from rayon
-        self.partitions.par_iter().enumerate().for_each(|(index, partition)|{
-            let particle_positions_mut = unsafe { &mut*(particle_positions_vec_addr as *mut Vec<glm::Vec2>) }; // Shhh
-            let particle_velocities_mut = unsafe { &mut*(particle_velocities_vec_addr as *mut Vec<glm::Vec2>) }; // Shhh
-            let particle_color_ids_mut = unsafe { &mut*(particle_color_ids_vec_addr as *mut Vec<u8>) }; // Shhh
-            
-            let mut other_partitions = Vec::with_capacity(9);
-            other_partitions.push((partition, glm::Vec2::zeros()));
-            
-            let y_i = index / self.cell_count;
-            let x_i = index - y_i * self.cell_count;
+        // Drag is applied exactly before integrating, the integrators only have to deal with the pairwise forces
+        let mut dragged_velocities: Vec<glm::Vec2> = self.particle_velocities.iter().map(|&velocity| velocity * drag).collect();
 
-            let w = self.cell_count;
-            let w_max = w-1;
+        let temperature = world_settings.thermal.temperature_at(self.time);
 
-            // Get neighbors
-            match world_settings.wrapping {
-                ParticleWrapping::Barrier => {
-                    if x_i >= 1 { // Left
-                        other_partitions.push((&self.partitions[index-1], glm::Vec2::zeros())); 
+        if world_settings.thermal.noise {
+            self.apply_noise(&mut dragged_velocities, delta_time, drag, temperature, world_settings, color_table);
+        }
 
-                        if y_i >= 1 { // Bottom Left
-                            other_partitions.push((&self.partitions[index-self.cell_count-1], glm::Vec2::zeros()));  
-                        }
-                        if y_i < self.cell_count - 1 { // Top Left
-                            other_partitions.push((&self.partitions[index+self.cell_count-1], glm::Vec2::zeros()));  
-                        }
-                    }
-                    if x_i < self.cell_count - 1 { // Right
-                        other_partitions.push((&self.partitions[index+1], glm::Vec2::zeros())); 
+        match particle_settings.integrator {
+            Integrator::Euler => {
+                let accelerations = self.apply_forces(&self.particle_positions, |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
 
-                        if y_i >= 1 { // Bottom Right
-                            other_partitions.push((&self.partitions[index-self.cell_count+1], glm::Vec2::zeros()));  
-                        }
-                        if y_i < self.cell_count - 1 { // Top Right
-                            other_partitions.push((&self.partitions[index+self.cell_count+1], glm::Vec2::zeros()));  
-                        }
-                    }
-                    if y_i >= 1 { // Bottom
-                        other_partitions.push((&self.partitions[index-self.cell_count], glm::Vec2::zeros()));  
-                    }
-                    if y_i < self.cell_count - 1 { // Top
-                        other_partitions.push((&self.partitions[index+self.cell_count], glm::Vec2::zeros()));  
-                    }
+                for (i, &velocity) in dragged_velocities.iter().enumerate() {
+                    self.particle_positions[i] += velocity * delta_time;
+                    self.particle_velocities[i] = velocity + accelerations[i] * delta_time;
                 }
-                ParticleWrapping::Wrap => {
-                    fn to_id(x: usize, y: usize, w: usize) -> usize {
-                        y * w + x
-                    }
-                    fn wrap(i: i32, max: usize) -> (usize, bool) {
-                        if i < 0 {
-                            ((max as i32 + i) as usize+1, true)
-                        }
-                        else if i as usize > max {
-                            (i as usize - max - 1, true)
-                        } else {
-                            (i as usize, false)
-                        }
-                    }
+            }
+            Integrator::SemiImplicitEuler => {
+                self.particle_velocities = self.apply_forces(&self.particle_positions, |index| dragged_velocities[index], delta_time, kernel, particle_settings, world_settings, color_table, &mut force_time);
 
-                    // Left
-                    let (p_x, wrapped) = wrap(x_i as i32 - 1, w_max);
-                    let offset = glm::Vec2::new(-self.size * wrapped as i32 as f32, 0.0);
-                    other_partitions.push((&self.partitions[to_id(p_x, y_i, w)], offset)); 
-                    
-                    // Bottom Left
-                    let (p_x, wrapped_x) = wrap(x_i as i32 - 1, w_max);
-                    let (p_y, wrapped_y) = wrap(y_i as i32 + 1, w_max);
-                    let offset = glm::Vec2::new(-self.size * wrapped_x as i32 as f32, self.size * wrapped_y as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(p_x, p_y, w)], offset)); 
-
-                    // Top Left
-                    let (p_x, wrapped_x) = wrap(x_i as i32 - 1, w_max);
-                    let (p_y, wrapped_y) = wrap(y_i as i32 - 1, w_max);
-                    let offset = glm::Vec2::new(-self.size * wrapped_x as i32 as f32, -self.size * wrapped_y as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(p_x, p_y, w)], offset)); 
-                    
-                    // Right
-                    let (p_x, wrapped) = wrap(x_i as i32 + 1, w_max);
-                    let offset = glm::Vec2::new(self.size * wrapped as i32 as f32, 0.0);
-                    other_partitions.push((&self.partitions[to_id(p_x, y_i, w)], offset)); 
-                    
-                    // Bottom Right
-                    let (p_x, wrapped_x) = wrap(x_i as i32 + 1, w_max);
-                    let (p_y, wrapped_y) = wrap(y_i as i32 + 1, w_max);
-                    let offset = glm::Vec2::new(self.size * wrapped_x as i32 as f32, self.size * wrapped_y as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(p_x, p_y, w)], offset)); 
-                
-                    // Top Right
-                    let (p_x, wrapped_x) = wrap(x_i as i32 + 1, w_max);
-                    let (p_y, wrapped_y) = wrap(y_i as i32 - 1, w_max);
-                    let offset = glm::Vec2::new(self.size * wrapped_x as i32 as f32, -self.size * wrapped_y as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(p_x, p_y, w)], offset)); 
-                    
-                    // Bottom
-                    let (p_y, wrapped) = wrap(y_i as i32 + 1, w_max);
-                    let offset = glm::Vec2::new(0.0, self.size * wrapped as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(x_i, p_y, w)], offset)); 
-                    
-                    // Top
-                    let (p_y, wrapped) = wrap(y_i as i32 - 1, w_max);
-                    let offset = glm::Vec2::new(0.0, -self.size * wrapped as i32 as f32);
-                    other_partitions.push((&self.partitions[to_id(x_i, p_y, w)], offset));   
+                for i in 0..self.particle_positions.len() {
+                    self.particle_positions[i] += self.particle_velocities[i] * delta_time;
                 }
             }
+            Integrator::VelocityVerlet => {
+                // Accelerations from the end of the last tick can be reused as long as neither the particles 
+                // nor anything else affecting the forces changed in between
+                if self.particle_accelerations.len() != self.particle_positions.len() || self.particle_accelerations_generation != self.force_generation {
+                    self.particle_accelerations = self.apply_forces(&self.particle_positions, |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+                }
 
-            for &index in &partition.particles {
-                particle_velocities_mut[index] *= drag.powf(delta_time);
-
-                for &(other_partition, offset) in &other_partitions{
-                    for &other_index in &other_partition.particles {
-                        let mut vec: glm::Vec2 = particle_positions_mut[other_index] - particle_positions_mut[index] + offset;
-                        let mut flt: f32 = vec.x*vec.x+vec.y*vec.y;
-    
-                        if flt == 0.0 {
-                            continue;
-                        } 
-
-                        // Reusing variables to save memory throughput
-                        flt = flt.sqrt();
-                        vec = vec / flt * particle_speed;
-                        
-                        let accel_multiplier;
-                        // https://www.desmos.com/calculator/xjmwts0q8l
-                        if flt > particle_settings.min_r {
-                            let c = color_table.table[particle_color_ids_mut[index] as usize][particle_color_ids_mut[other_index] as usize];
-                            // Old equation was removed because it was too costly: ((PI*(dist - particle_settings.min_r)) / (particle_settings.max_r - particle_settings.min_r)).sin();
-                            let v = 1.0 - (1.0 + min_r_norm - 2.0 * (flt/particle_settings.max_r).min(1.0)).abs() / (1.0 - min_r_norm);
-                            
-                            accel_multiplier = c * v;
-                        } else {
-                            accel_multiplier = flt / particle_settings.min_r - 1.0;
-                        }
+                for (i, &velocity) in dragged_velocities.iter().enumerate() {
+                    self.particle_positions[i] += velocity * delta_time + self.particle_accelerations[i] * (0.5 * delta_time * delta_time);
+                }
 
-                        particle_velocities_mut[index] += vec * accel_multiplier;
-                    }
+                // The boundaries may flip or reset velocities, so they have to be applied before the second half kick
+                self.particle_velocities = dragged_velocities;
+                self.apply_boundaries(world_settings);
+                self.rebuild_partitions();
+
+                let accelerations = self.apply_forces(&self.particle_positions, |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+
+                for (i, velocity) in self.particle_velocities.iter_mut().enumerate() {
+                    *velocity += (self.particle_accelerations[i] + accelerations[i]) * (0.5 * delta_time);
                 }
+
+                self.particle_accelerations = accelerations;
+                self.particle_accelerations_generation = self.force_generation;
             }
-        });
+            Integrator::RK4 => {
+                let half_step = delta_time * 0.5;
+                let count = self.particle_positions.len();
 
-        self.velocity_update_time = start.elapsed().as_secs_f32()*1000.0;
+                let offset_positions = |velocities: &[glm::Vec2], step: f32| -> Vec<glm::Vec2> {
+                    (0..count).map(|i| self.particle_positions[i] + velocities[i] * step).collect()
+                };
+                let offset_velocities = |accelerations: &[glm::Vec2], step: f32| -> Vec<glm::Vec2> {
+                    (0..count).map(|i| dragged_velocities[i] + accelerations[i] * step).collect()
+                };
 
-        let start = std::time::Instant::now();
+                let k1_v = self.apply_forces(&self.particle_positions, |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+                let k1_x = &dragged_velocities;
 
-        let barrier = self.half_size;
+                let k2_v = self.apply_forces(&offset_positions(k1_x, half_step), |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+                let k2_x = offset_velocities(&k1_v, half_step);
 
-        for i in 0..self.particle_velocities.len() {
-            self.particle_positions[i] += self.particle_velocities[i] * delta_time;
-        }
+                let k3_v = self.apply_forces(&offset_positions(&k2_x, half_step), |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+                let k3_x = offset_velocities(&k2_v, half_step);
 
-        match world_settings.wrapping {
-            ParticleWrapping::Wrap => {
-                self.particle_positions.iter_mut().for_each(|position| {
-                    if position.x > barrier-BARRIER_MARGIN {
-                        position.x = -barrier+BARRIER_MARGIN*2.0;
-                    } if position.x < -barrier+BARRIER_MARGIN {
-                        position.x = barrier-BARRIER_MARGIN*2.0;
-                    }
-    
-                    if position.y > barrier-BARRIER_MARGIN {
-                        position.y = -barrier+BARRIER_MARGIN*2.0;
-                    } if position.y < -barrier+BARRIER_MARGIN {
-                        position.y = barrier-BARRIER_MARGIN*2.0;
-                    }
-                });
-            }
-            ParticleWrapping::Barrier => {
-                self.particle_positions.iter_mut().for_each(|position| {
-                    position.x = position.x.clamp(-barrier+BARRIER_MARGIN, barrier-BARRIER_MARGIN);
-                    position.y = position.y.clamp(-barrier+BARRIER_MARGIN, barrier-BARRIER_MARGIN);
-                });
+                let k4_v = self.apply_forces(&offset_positions(&k3_x, delta_time), |_| glm::Vec2::zeros(), 1.0, kernel, particle_settings, world_settings, color_table, &mut force_time);
+                let k4_x = offset_velocities(&k3_v, delta_time);
+
+                let sixth_step = delta_time / 6.0;
+
+                for (i, &velocity) in dragged_velocities.iter().enumerate() {
+                    self.particle_positions[i] += (k1_x[i] + (k2_x[i] + k3_x[i]) * 2.0 + k4_x[i]) * sixth_step;
+                    self.particle_velocities[i] = velocity + (k1_v[i] + (k2_v[i] + k3_v[i]) * 2.0 + k4_v[i]) * sixth_step;
+                }
             }
         }
 
-        self.position_update_time = start.elapsed().as_secs_f32()*1000.0;
+        // Velocity Verlet already applied the boundaries before its second half kick
+        if particle_settings.integrator != Integrator::VelocityVerlet {
+            self.particle_accelerations.clear();
+            self.apply_boundaries(world_settings);
+        }
+
+        if world_settings.thermal.thermostat != Thermostat::None {
+            self.apply_thermostat(delta_time, temperature, world_settings, color_table);
+        }
+
+        self.tick += 1;
+        self.time += delta_time;
+
+        self.velocity_update_time = force_time;
+        self.position_update_time = start.elapsed().as_secs_f32()*1000.0 - force_time;
     }
 
-    pub fn new_partitions(&mut self, world_settings: &WorldSettings, particle_settings: &ParticleSettings) {
-        let world_size = world_settings.size;
-        let cell_size = particle_settings.max_r;
+    fn update_pair_rows(&mut self, particle_settings: &ParticleSettings, color_table: &ColorTable) {
+        let inputs = (color_table.clone(), particle_settings.min_r, particle_settings.max_r);
 
-        // The cell size always has to be equal or greater to particle_max_r (Max influnce radius of a particle)
-        let cell_count_floor = (world_size / cell_size).floor() as usize;
-        let cell_size = world_size / cell_count_floor as f32;
+        if self.pair_rows_inputs.as_ref() == Some(&inputs) {
+            return;
+        }
 
-        let cell_count = (world_size / cell_size).ceil() as usize;
+        let color_count = color_table.colors.len();
 
-        self.size = world_size;
-        self.half_size = world_size / 2.0;
-        self.partitions = vec![PartitionCell::new(); cell_count*cell_count];
-        self.cell_count = cell_count;
-        self.cell_size = cell_size;
+        self.pair_rows = (0..color_count).map(|color_id| {
+            let row = |value: &dyn Fn(usize) -> f32| std::array::from_fn(|other_color_id| {
+                if other_color_id < color_count { value(other_color_id) } else { 0.0 }
+            });
 
-        self.particle_positions.iter_mut().for_each(|position| {
-            if position.x > self.half_size-BARRIER_MARGIN {
-                position.x = -self.half_size+BARRIER_MARGIN*2.0;
-            } if position.x < -self.half_size+BARRIER_MARGIN {
-                position.x = self.half_size-0.2;
+            PairRow {
+                attraction: row(&|other_color_id| color_table.table[color_id][other_color_id]),
+                min_r: row(&|other_color_id| color_table.get_min_r(color_id, other_color_id, particle_settings.min_r)),
+                max_r: row(&|other_color_id| color_table.get_max_r(color_id, other_color_id, particle_settings.max_r)),
             }
+        }).collect();
 
-            if position.y > self.half_size-BARRIER_MARGIN {
-                position.y = -self.half_size+BARRIER_MARGIN*2.0;
-            } if position.y < -self.half_size+BARRIER_MARGIN {
-                position.y = self.half_size-BARRIER_MARGIN*2.0;
-            }
-        });
+        self.pair_rows_inputs = Some(inputs);
     }
 
-    pub fn update_partitions(&mut self) {
+    // Returns `initial(index) + time_scale * acceleration` for every particle at the given positions. 
+    // Positions, colors and partitions are only read while the results are written to a separate buffer, 
+    // so every particle is processed independently and in a fixed order regardless of the thread count
+    #[allow(clippy::too_many_arguments)]
+    fn apply_forces<K: ForceKernel + Sync>(
+        &self,
+        positions: &[glm::Vec2],
+        initial: impl Fn(usize) -> glm::Vec2 + Sync,
+        time_scale: f32,
+        kernel: &K,
+        particle_settings: &ParticleSettings,
+        world_settings: &WorldSettings,
+        color_table: &ColorTable,
+        time: &mut f32
+    ) -> Vec<glm::Vec2> {
         let start = std::time::Instant::now();
 
-        self.partitions.iter_mut().for_each(|partition|{
-            partition.particles.clear();
-        });
-    
-        self.particle_positions.clone().iter().enumerate().for_each(|(index, position)| {
-            let id = self.get_partition_id(&position);
+        let scale = 75.0 * particle_settings.force * time_scale;
+        let soft_walls = world_settings.wrapping == ParticleWrapping::SoftWall;
+        let obstacle_repulsion = self.scene.repulsion != 0.0 && !self.scene.obstacles.is_empty();
 
-            self.partitions[id].particles.push(index);
-        });
+        // Structure of arrays, so the lanes can be loaded without shuffling the coordinates apart
+        let (xs, ys): (Vec<f32>, Vec<f32>) = positions.iter().map(|position| (position.x, position.y)).unzip();
 
-        self.partition_update_time = start.elapsed().as_secs_f32()*1000.0;
-    }
+        // Partitions holding a run of consecutive indices, which is all of them right after `update_partitions`
+        let contiguous: Vec<bool> = (0..self.partitions.len()).map(|partition| {
+            self.get_partition_particles(partition).windows(2).all(|pair| pair[1] == pair[0] + 1)
+        }).collect();
 
-    pub fn get_closest_particle_id(&self, pos: &glm::Vec2) -> Option<usize> {
-        if pos.x.abs() >= self.half_size || pos.y.abs() >= self.half_size {
-           return None;
-        }
+        // The Newtonian mode evaluates the pairs per partition pair instead, always in the scalar loop
+        let pair_accelerations = if particle_settings.newtonian {
+            self.apply_pair_forces(positions, scale, kernel, particle_settings, color_table)
+        } else {
+            Vec::new()
+        };
+
+        let result = (0..positions.len()).into_par_iter().map(|index| {
+            let position = positions[index];
+            let color_id = self.particle_color_ids[index] as usize;
 
-        let partition = &self.partitions[self.get_partition_id(pos)];
+            // Forces are turned into accelerations by dividing them by the mass
+            let scale = scale / (color_table.masses[color_id] * self.particle_mass_factors[index]);
 
-        let mut closest_index = 0;
-        let mut closest_distance = f32::INFINITY;
+            let row = &self.pair_rows[color_id];
 
-        for (index, position) in partition.particles.iter().map(|&index| (index, &self.particle_positions[index])) {
-            let diff = position - pos;
-            let sq_dist = diff.x*diff.x+diff.y*diff.y;
+            let (position_x, position_y, scale_lanes) = (Lanes::splat(position.x), Lanes::splat(position.y), Lanes::splat(scale));
 
-            if sq_dist < closest_distance {
-                closest_distance = sq_dist;
-                closest_index = index;
+            let mut velocity = initial(index);
+
+            if particle_settings.newtonian {
+                velocity += pair_accelerations[index];
             }
-        }
 
-        Some(closest_index)
+            let neighbors: &[PartitionNeighbor] = if particle_settings.newtonian {
+                &[]
+            } else {
+                &self.partition_neighbors[self.particle_partition_ids[index]]
+            };
+
+            for neighbor in neighbors {
+                let mut others = self.get_partition_particles(neighbor.partition);
+
+                let (mirror_x, mirror_y) = (Lanes::splat(neighbor.mirror.x), Lanes::splat(neighbor.mirror.y));
+                let (offset_x, offset_y) = (Lanes::splat(neighbor.offset.x), Lanes::splat(neighbor.offset.y));
+
+                let mut add_lanes = |x: Lanes, y: Lanes, other_color_ids: &[u8]| {
+                    let vec_x = x * mirror_x - position_x + offset_x;
+                    let vec_y = y * mirror_y - position_y + offset_y;
+
+                    let distance_sq = vec_x*vec_x + vec_y*vec_y;
+                    let distance = distance_sq.sqrt();
+
+                    let gather = |row: &[f32; 256]| Lanes::from(std::array::from_fn::<f32, LANES, _>(|lane| row[other_color_ids[lane] as usize]));
+
+                    let multiplier = kernel.force_lanes(distance, gather(&row.min_r), gather(&row.max_r), gather(&row.attraction));
+
+                    let force_x = (vec_x / distance * scale_lanes * multiplier).to_array();
+                    let force_y = (vec_y / distance * scale_lanes * multiplier).to_array();
+                    let distance_sq = distance_sq.to_array();
+
+                    // Summed one by one in the same order as the scalar loop, so both give bit identical results. 
+                    // Overlapping particles have no direction and are skipped like there
+                    for lane in 0..LANES {
+                        if distance_sq[lane] != 0.0 {
+                            velocity.x += force_x[lane];
+                            velocity.y += force_y[lane];
+                        }
+                    }
+                };
+
+                if particle_settings.simd && contiguous[neighbor.partition] && !others.is_empty() {
+                    let range = others[0]..others[0] + others.len();
+
+                    let x_chunks = xs[range.clone()].chunks_exact(LANES);
+                    let y_chunks = ys[range.clone()].chunks_exact(LANES);
+                    let color_chunks = self.particle_color_ids[range].chunks_exact(LANES);
+
+                    let remaining = x_chunks.remainder().len();
+
+                    for ((x, y), other_color_ids) in x_chunks.zip(y_chunks).zip(color_chunks) {
+                        add_lanes(Lanes::from(<[f32; LANES]>::try_from(x).unwrap()), Lanes::from(<[f32; LANES]>::try_from(y).unwrap()), other_color_ids);
+                    }
+
+                    others = &others[others.len() - remaining..];
+                } else if particle_settings.simd {
+                    let mut chunks = others.chunks_exact(LANES);
+
+                    for chunk in &mut chunks {
+                        add_lanes(
+                            Lanes::from(std::array::from_fn::<f32, LANES, _>(|lane| xs[chunk[lane]])),
+                            Lanes::from(std::array::from_fn::<f32, LANES, _>(|lane| ys[chunk[lane]])),
+                            &std::array::from_fn::<u8, LANES, _>(|lane| self.particle_color_ids[chunk[lane]])
+                        );
+                    }
+
+                    others = chunks.remainder();
+                }
+
+                for &other_index in others {
+                    let mut vec: glm::Vec2 = positions[other_index].component_mul(&neighbor.mirror) - position + neighbor.offset;
+                    let mut flt: f32 = vec.x*vec.x+vec.y*vec.y;
+
+                    if flt == 0.0 {
+                        continue;
+                    } 
+
+                    // Reusing variables to save memory throughput
+                    flt = flt.sqrt();
+                    vec = vec / flt * scale;
+                    
+                    let other_color_id = self.particle_color_ids[other_index] as usize;
+
+                    let accel_multiplier = kernel.force(flt, row.min_r[other_color_id], row.max_r[other_color_id], row.attraction[other_color_id]);
+
+                    velocity += vec * accel_multiplier;
+                }
+            }
+
+            // Walls and fields are accelerations, so they affect every particle the same regardless of its mass
+            if soft_walls {
+                velocity += self.wall_acceleration(&position, world_settings) * time_scale;
+            }
+            for field in &world_settings.force_fields {
+                velocity += field.acceleration(&position, &self.size) * time_scale;
+            }
+            if obstacle_repulsion {
+                velocity += self.obstacle_acceleration(&position) * time_scale;
+            }
+            if let Some(interaction) = &self.interaction {
+                velocity += interaction.acceleration(&position, &self.particle_velocities[index]) * time_scale;
+            }
+
+            velocity
+        }).collect();
+
+        *time += start.elapsed().as_secs_f32()*1000.0;
+
+        result
+    }
+
+    // Newtonian mode, every pair is evaluated once with the symmetrised table and radii, and both particles get 
+    // equal and opposite forces. Each partition writes the reactions on its neighbors to its own buffers, 
+    // which are added up in the order of the partitions afterwards, so the result doesn't depend on the thread count
+    fn apply_pair_forces<K: ForceKernel + Sync>(
+        &self,
+        positions: &[glm::Vec2],
+        scale: f32,
+        kernel: &K,
+        particle_settings: &ParticleSettings,
+        color_table: &ColorTable
+    ) -> Vec<glm::Vec2> {
+        let color_count = color_table.colors.len();
+
+        let pairs: Vec<(f32, f32, f32)> = (0..color_count*color_count).map(|i| {
+            let (a, b) = (i / color_count, i % color_count);
+
+            (
+                (color_table.table[a][b] + color_table.table[b][a]) * 0.5,
+                (color_table.get_min_r(a, b, particle_settings.min_r) + color_table.get_min_r(b, a, particle_settings.min_r)) * 0.5,
+                (color_table.get_max_r(a, b, particle_settings.max_r) + color_table.get_max_r(b, a, particle_settings.max_r)) * 0.5
+            )
+        }).collect();
+
+        let masses: Vec<f32> = (0..positions.len()).map(|index| {
+            color_table.masses[self.particle_color_ids[index] as usize] * self.particle_mass_factors[index]
+        }).collect();
+
+        let force = |index: usize, other_index: usize, other_position: glm::Vec2| -> Option<glm::Vec2> {
+            let vec = other_position - positions[index];
+            let distance_sq = vec.x*vec.x+vec.y*vec.y;
+
+            if distance_sq == 0.0 {
+                return None;
+            }
+
+            let distance = distance_sq.sqrt();
+            let (c, min_r, max_r) = pairs[self.particle_color_ids[index] as usize * color_count + self.particle_color_ids[other_index] as usize];
+
+            Some(vec / distance * scale * kernel.force(distance, min_r, max_r, c))
+        };
+
+        let cells: Vec<(Vec<glm::Vec2>, Vec<Vec<glm::Vec2>>)> = (0..self.partitions.len()).into_par_iter().map(|partition| {
+            let particles = self.get_partition_particles(partition);
+            let mut own = vec![glm::Vec2::zeros(); particles.len()];
+
+            for (i, &index) in particles.iter().enumerate() {
+                for (j, &other_index) in particles.iter().enumerate().skip(i + 1) {
+                    if let Some(force) = force(index, other_index, positions[other_index]) {
+                        own[i] += force / masses[index];
+                        own[j] -= force / masses[other_index];
+                    }
+                }
+            }
+
+            let reactions = self.partition_half_neighbors[partition].iter().map(|neighbor| {
+                let others = self.get_partition_particles(neighbor.partition);
+                let mut reaction = vec![glm::Vec2::zeros(); others.len()];
+
+                for (i, &index) in particles.iter().enumerate() {
+                    for (j, &other_index) in others.iter().enumerate() {
+                        let other_position = positions[other_index].component_mul(&neighbor.mirror) + neighbor.offset;
+
+                        // The reaction is mirrored back into the neighbor's own orientation
+                        if let Some(force) = force(index, other_index, other_position) {
+                            own[i] += force / masses[index];
+                            reaction[j] -= force.component_mul(&neighbor.mirror) / masses[other_index];
+                        }
+                    }
+                }
+
+                reaction
+            }).collect();
+
+            (own, reactions)
+        }).collect();
+
+        let mut accelerations = vec![glm::Vec2::zeros(); positions.len()];
+
+        for (partition, (own, reactions)) in cells.into_iter().enumerate() {
+            for (&index, acceleration) in self.get_partition_particles(partition).iter().zip(own) {
+                accelerations[index] += acceleration;
+            }
+            for (neighbor, reaction) in self.partition_half_neighbors[partition].iter().zip(reactions) {
+                for (&index, acceleration) in self.get_partition_particles(neighbor.partition).iter().zip(reaction) {
+                    accelerations[index] += acceleration;
+                }
+            }
+        }
+
+        accelerations
+    }
+
+    // Random kicks that the drag takes away again at the same rate once the particles are at the temperature, 
+    // like an Ornstein-Uhlenbeck process. With little or no drag the kicks are those of `MIN_NOISE_FRICTION`, 
+    // about `sqrt(2 * temperature * delta_time / mass)` like Langevin dynamics, and only a thermostat limits the temperature. 
+    // The kicks depend on the particle ids, so they don't change with the memory layout
+    fn apply_noise(&self, velocities: &mut [glm::Vec2], delta_time: f32, drag: f32, temperature: f32, world_settings: &WorldSettings, color_table: &ColorTable) {
+        let seed = world_settings.sub_seed_hash("noise");
+
+        // `drag` is the velocity left after this substep, `exp(-friction * delta_time)`
+        let friction = (-drag.ln() / delta_time.max(f32::EPSILON)).max(MIN_NOISE_FRICTION);
+        let variance = temperature.max(0.0) * (1.0 - (-2.0 * friction * delta_time).exp());
+
+        velocities.par_iter_mut().enumerate().for_each(|(index, velocity)| {
+            let id = self.particle_ids[index];
+
+            // Box-Muller transform, one normally distributed number for each axis
+            let radius = (-2.0 * (1.0 - hash_random(seed, self.tick, id, 0)).ln()).sqrt();
+            let angle = hash_random(seed, self.tick, id, 1) * std::f32::consts::TAU;
+
+            let deviation = (variance / self.get_particle_mass(index, color_table)).sqrt();
+
+            *velocity += glm::Vec2::new(angle.cos(), angle.sin()) * radius * deviation;
+        });
+    }
+
+    fn apply_thermostat(&mut self, delta_time: f32, temperature: f32, world_settings: &WorldSettings, color_table: &ColorTable) {
+        let current = self.get_temperature(color_table);
+
+        if current <= 0.0 {
+            return;
+        }
+
+        let ratio = temperature.max(0.0) / current;
+
+        let scale_sq = match world_settings.thermal.thermostat {
+            Thermostat::None => 1.0,
+            Thermostat::Berendsen => 1.0 + (delta_time / world_settings.thermal.coupling_time.max(delta_time)) * (ratio - 1.0),
+            Thermostat::VelocityRescaling => ratio,
+        };
+
+        let scale = scale_sq.max(0.0).sqrt();
+
+        self.particle_velocities.par_iter_mut().for_each(|velocity| *velocity *= scale);
+    }
+
+    // Changes the colors of the particles according to the color table's transmutation rules. 
+    // Like the forces, the new colors only depend on the state at the start of the tick
+    fn apply_transmutations(&mut self, delta_time: f32, world_settings: &WorldSettings, color_table: &ColorTable) {
+        let color_count = color_table.colors.len() as u8;
+        let seed = world_settings.sub_seed_hash("transmutation");
+
+        let rules: Vec<_> = color_table.transmutations.iter().filter(|rule| {
+            rule.from < color_count && rule.catalyst < color_count && rule.to < color_count
+        }).collect();
+
+        // Probability of a rule firing during this tick
+        let chances: Vec<f32> = rules.iter().map(|rule| {
+            1.0 - (1.0 - rule.probability.clamp(0.0, 1.0)).powf(delta_time)
+        }).collect();
+
+        let new_color_ids: Vec<u8> = (0..self.particle_positions.len()).into_par_iter().map(|index| {
+            let position = self.particle_positions[index];
+            let color_id = self.particle_color_ids[index];
+
+            if !rules.iter().any(|rule| rule.from == color_id) {
+                return color_id;
+            }
+
+            let mut in_contact = vec![false; rules.len()];
+
+            for neighbor in &self.partition_neighbors[self.particle_partition_ids[index]] {
+                for &other_index in self.get_partition_particles(neighbor.partition) {
+                    let vec: glm::Vec2 = self.particle_positions[other_index].component_mul(&neighbor.mirror) - position + neighbor.offset;
+                    let distance_sq = vec.x*vec.x+vec.y*vec.y;
+
+                    if distance_sq == 0.0 {
+                        continue;
+                    }
+
+                    let other_color_id = self.particle_color_ids[other_index];
+
+                    for (rule_index, rule) in rules.iter().enumerate() {
+                        if rule.from == color_id && rule.catalyst == other_color_id && distance_sq <= rule.radius*rule.radius {
+                            in_contact[rule_index] = true;
+                        }
+                    }
+                }
+            }
+
+            for (rule_index, rule) in rules.iter().enumerate() {
+                if in_contact[rule_index] && hash_random(seed, self.tick, self.particle_ids[index], rule_index) < chances[rule_index] {
+                    return rule.to;
+                }
+            }
+
+            color_id
+        }).collect();
+
+        if new_color_ids != self.particle_color_ids {
+            self.particle_color_ids = new_color_ids;
+            self.particle_accelerations.clear();
+        }
+    }
+
+    // Feeds the particles, then removes the ones without any energy left and divides the ones with enough of it. 
+    // Offspring are appended in the order of their parents, so the result doesn't depend on the thread count
+    fn apply_ecosystem(&mut self, delta_time: f32, particle_settings: &ParticleSettings, world_settings: &WorldSettings, color_table: &ColorTable) {
+        let ecosystem = &world_settings.ecosystem;
+        let color_count = color_table.colors.len();
+
+        // Nothing to feed on and no metabolism to look up
+        if color_count == 0 {
+            return;
+        }
+
+        self.particle_energies = (0..self.particle_positions.len()).into_par_iter().map(|index| {
+            let position = self.particle_positions[index];
+            let color_id = self.particle_color_ids[index] as usize;
+
+            let mut gain = -color_table.metabolism[color_id];
+
+            for neighbor in &self.partition_neighbors[self.particle_partition_ids[index]] {
+                for &other_index in self.get_partition_particles(neighbor.partition) {
+                    let vec: glm::Vec2 = self.particle_positions[other_index].component_mul(&neighbor.mirror) - position + neighbor.offset;
+                    let distance_sq = vec.x*vec.x+vec.y*vec.y;
+
+                    if distance_sq == 0.0 {
+                        continue;
+                    }
+
+                    let other_color_id = (self.particle_color_ids[other_index] as usize).min(color_count - 1);
+                    let max_r = color_table.get_max_r(color_id, other_color_id, particle_settings.max_r);
+
+                    if distance_sq <= max_r*max_r {
+                        gain += color_table.food[color_id][other_color_id];
+                    }
+                }
+            }
+
+            self.particle_energies[index] + gain * delta_time
+        }).collect();
+
+        let alive: Vec<bool> = self.particle_energies.iter().map(|&energy| energy > 0.0).collect();
+        let died = self.retain_particles(&alive);
+
+        let seed = world_settings.sub_seed_hash("ecosystem");
+        let limit = self.half_size - glm::Vec2::new(BARRIER_MARGIN, BARRIER_MARGIN);
+        let mut born = 0;
+
+        for index in 0..self.particle_positions.len() {
+            if self.particle_positions.len() >= ecosystem.max_population {
+                break;
+            }
+            if self.particle_energies[index] < ecosystem.division_energy {
+                continue;
+            }
+
+            let color_id = self.particle_color_ids[index];
+
+            // The offspring is placed right at the repulsion radius, so the two get pushed apart
+            let angle = hash_random(seed, self.tick, self.particle_ids[index], 0) * std::f32::consts::TAU;
+            let distance = color_table.get_min_r(color_id as usize, color_id as usize, particle_settings.min_r) * 0.5;
+            let position = self.particle_positions[index] + glm::Vec2::new(angle.cos(), angle.sin()) * distance;
+
+            self.particle_energies[index] *= 0.5;
+
+            self.push_particle(
+                glm::clamp_vec(&position, &-limit, &limit),
+                self.particle_velocities[index],
+                color_id,
+                self.particle_mass_factors[index],
+                self.particle_energies[index]
+            );
+
+            born += 1;
+        }
+
+        if died > 0 || born > 0 {
+            self.particles_changed();
+        }
+    }
+
+    // Pushes the particles back inside when they get closer than `wall_range` to an edge
+    fn wall_acceleration(&self, position: &glm::Vec2, world_settings: &WorldSettings) -> glm::Vec2 {
+        let wall = |position: f32, half_size: f32| {
+            let depth = world_settings.wall_range - (half_size - position.abs());
+
+            if depth > 0.0 {
+                -position.signum() * world_settings.wall_stiffness * (depth / world_settings.wall_range).min(1.0)
+            } else {
+                0.0
+            }
+        };
+
+        glm::Vec2::new(wall(position.x, self.half_size.x), wall(position.y, self.half_size.y))
+    }
+
+    fn obstacle_acceleration(&self, position: &glm::Vec2) -> glm::Vec2 {
+        self.scene.obstacles.iter().fold(glm::Vec2::zeros(), |acceleration, obstacle| {
+            let (distance, normal) = obstacle.signed_distance(position);
+
+            if distance < self.scene.repulsion_range {
+                acceleration + normal * self.scene.repulsion * (1.0 - distance.max(0.0) / self.scene.repulsion_range)
+            } else {
+                acceleration
+            }
+        })
+    }
+
+    // Pushes the particles inside obstacles back out to the surface and bounces them off it
+    fn apply_obstacles(&mut self) {
+        let scene = &self.scene;
+
+        self.particle_positions.par_iter_mut().zip(self.particle_velocities.par_iter_mut()).for_each(|(position, velocity)| {
+            for obstacle in &scene.obstacles {
+                let (distance, normal) = obstacle.signed_distance(position);
+
+                if distance < BARRIER_MARGIN {
+                    *position += normal * (BARRIER_MARGIN - distance);
+
+                    let normal_velocity = glm::dot(velocity, &normal);
+
+                    if normal_velocity < 0.0 {
+                        *velocity -= normal * normal_velocity * (1.0 + scene.restitution);
+                    }
+                }
+            }
+        });
+    }
+
+    // Teleports a coordinate that left the world to the opposite edge, returns whether it did
+    fn wrap_coordinate(value: &mut f32, half_size: f32) -> bool {
+        if *value > half_size-BARRIER_MARGIN {
+            *value = -half_size+BARRIER_MARGIN*2.0;
+        } else if *value < -half_size+BARRIER_MARGIN {
+            *value = half_size-BARRIER_MARGIN*2.0;
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    // Mirrors a coordinate that left the world back inside, returns whether it did
+    fn reflect_coordinate(value: &mut f32, half_size: f32) -> bool {
+        let limit = half_size-BARRIER_MARGIN;
+
+        if *value > limit {
+            *value = (2.0*limit - *value).max(-limit);
+        } else if *value < -limit {
+            *value = (-2.0*limit - *value).min(limit);
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    fn apply_boundaries(&mut self, world_settings: &WorldSettings) {
+        let barrier = self.half_size;
+
+        let positions = self.particle_positions.iter_mut();
+        let velocities = self.particle_velocities.iter_mut();
+
+        match world_settings.wrapping {
+            ParticleWrapping::Wrap => {
+                positions.for_each(|position| {
+                    Self::wrap_coordinate(&mut position.x, barrier.x);
+                    Self::wrap_coordinate(&mut position.y, barrier.y);
+                });
+            }
+            ParticleWrapping::Barrier | ParticleWrapping::SoftWall => {
+                positions.for_each(|position| {
+                    position.x = position.x.clamp(-barrier.x+BARRIER_MARGIN, barrier.x-BARRIER_MARGIN);
+                    position.y = position.y.clamp(-barrier.y+BARRIER_MARGIN, barrier.y-BARRIER_MARGIN);
+                });
+            }
+            ParticleWrapping::Reflect => {
+                positions.zip(velocities).for_each(|(position, velocity)| {
+                    if Self::reflect_coordinate(&mut position.x, barrier.x) {
+                        velocity.x = -velocity.x;
+                    }
+                    if Self::reflect_coordinate(&mut position.y, barrier.y) {
+                        velocity.y = -velocity.y;
+                    }
+                });
+            }
+            ParticleWrapping::Absorb => {
+                // Seeded by the tick, so the respawn positions are reproducible
+                let mut r = StdRng::seed_from_u64(world_settings.sub_seed_hash("absorb") ^ self.tick);
+
+                positions.zip(velocities).for_each(|(position, velocity)| {
+                    if position.x.abs() > barrier.x-BARRIER_MARGIN || position.y.abs() > barrier.y-BARRIER_MARGIN {
+                        *position = glm::Vec2::new(
+                            r.gen_range(-barrier.x+BARRIER_MARGIN..=barrier.x-BARRIER_MARGIN),
+                            r.gen_range(-barrier.y+BARRIER_MARGIN..=barrier.y-BARRIER_MARGIN)
+                        );
+                        *velocity = glm::Vec2::zeros();
+                    }
+                });
+            }
+            ParticleWrapping::KleinBottle => {
+                positions.zip(velocities).for_each(|(position, velocity)| {
+                    Self::wrap_coordinate(&mut position.x, barrier.x);
+
+                    if Self::wrap_coordinate(&mut position.y, barrier.y) {
+                        position.x = -position.x;
+                        velocity.x = -velocity.x;
+                    }
+                });
+            }
+            ParticleWrapping::MobiusStrip => {
+                positions.zip(velocities).for_each(|(position, velocity)| {
+                    position.y = position.y.clamp(-barrier.y+BARRIER_MARGIN, barrier.y-BARRIER_MARGIN);
+
+                    if Self::wrap_coordinate(&mut position.x, barrier.x) {
+                        position.y = -position.y;
+                        velocity.y = -velocity.y;
+                    }
+                });
+            }
+        }
+
+        if !self.scene.obstacles.is_empty() {
+            self.apply_obstacles();
+        }
+    }
+
+    pub fn new_partitions(&mut self, world_settings: &WorldSettings, particle_settings: &ParticleSettings, color_table: &ColorTable) {
+        let world_size = glm::Vec2::new(world_settings.width, world_settings.height);
+        let max_r = color_table.largest_max_r(particle_settings.max_r);
+
+        // The cell size always has to be equal or greater to the largest max_r (Max influnce radius of a particle)
+        let cell_count_x = ((world_size.x / max_r).floor() as usize).max(1);
+        let cell_count_y = ((world_size.y / max_r).floor() as usize).max(1);
+
+        self.size = world_size;
+        self.half_size = world_size / 2.0;
+        self.partitions = vec![PartitionCell::default(); cell_count_x*cell_count_y];
+        self.cell_count_x = cell_count_x;
+        self.cell_count_y = cell_count_y;
+
+        self.partition_order = (0..cell_count_x*cell_count_y).collect();
+
+        if world_settings.morton_order {
+            self.partition_order.sort_by_key(|&partition| Self::morton_code(partition % cell_count_x, partition / cell_count_x));
+        }
+        self.cell_size = glm::Vec2::new(world_size.x / cell_count_x as f32, world_size.y / cell_count_y as f32);
+
+        let half_size = self.half_size;
+
+        self.particle_positions.iter_mut().for_each(|position| {
+            Self::wrap_coordinate(&mut position.x, half_size.x);
+            Self::wrap_coordinate(&mut position.y, half_size.y);
+        });
+
+        self.particles_changed();
+    }
+
+    // Also reorders the particles by partition, so the particle indices change with every call
+    pub fn update_partitions(&mut self) {
+        let start = std::time::Instant::now();
+
+        self.rebuild_partitions();
+        self.reorder_particles();
+
+        self.partition_update_time = start.elapsed().as_secs_f32()*1000.0;
+    }
+
+    // Counting sort of the particle indices by partition
+    fn rebuild_partitions(&mut self) {
+        self.particle_partition_ids = self.particle_positions.iter().map(|position| {
+            self.get_partition_id(position)
+        }).collect();
+
+        let mut counts = vec![0; self.partitions.len()];
+
+        for &id in &self.particle_partition_ids {
+            counts[id] += 1;
+        }
+
+        let mut start = 0;
+
+        for &partition in &self.partition_order {
+            self.partitions[partition] = PartitionCell { start, end: start };
+            start += counts[partition];
+        }
+
+        self.partition_particles.resize(self.particle_positions.len(), 0);
+
+        for (index, &id) in self.particle_partition_ids.iter().enumerate() {
+            let partition = &mut self.partitions[id];
+
+            self.partition_particles[partition.end] = index;
+            partition.end += 1;
+        }
+
+        // The forces are summed in this order, so sorting by id keeps the results independent of the memory layout. 
+        // The partitions are mostly made of a few already sorted runs, which the stable sort merges quickly
+        let particle_ids = &self.particle_ids;
+
+        for partition in &self.partitions {
+            self.partition_particles[partition.start..partition.end].sort_by_key(|&index| particle_ids[index]);
+        }
+    }
+
+    // Moves the particles in memory to the order of `partition_particles`, 
+    // so the particles of a partition and of its neighbors are next to each other
+    fn reorder_particles(&mut self) {
+        if self.partition_particles.iter().enumerate().all(|(index, &other_index)| index == other_index) {
+            return;
+        }
+
+        fn reorder<T: Copy + Send + Sync>(values: &mut Vec<T>, order: &[usize]) {
+            *values = order.par_iter().map(|&index| values[index]).collect();
+        }
+
+        let order = &self.partition_particles;
+
+        reorder(&mut self.particle_positions, order);
+        reorder(&mut self.particle_velocities, order);
+        reorder(&mut self.particle_color_ids, order);
+        reorder(&mut self.particle_mass_factors, order);
+        reorder(&mut self.particle_energies, order);
+        reorder(&mut self.particle_ids, order);
+        reorder(&mut self.particle_partition_ids, order);
+
+        // Only the indices of the same ids changed
+        for (index, &id) in self.particle_ids.iter().enumerate() {
+            self.particle_indices[id] = index;
+        }
+
+        // Cached by the Velocity Verlet integrator
+        if self.particle_accelerations.len() == order.len() {
+            reorder(&mut self.particle_accelerations, order);
+        }
+
+        self.partition_particles.iter_mut().enumerate().for_each(|(index, other_index)| *other_index = index);
+    }
+
+    fn get_partition_particles(&self, partition: usize) -> &[usize] {
+        let partition = self.partitions[partition];
+
+        &self.partition_particles[partition.start..partition.end]
+    }
+
+    // Interleaves the bits of the coordinates, so partitions close to each other mostly get close codes
+    fn morton_code(x: usize, y: usize) -> u64 {
+        let spread = |value: usize| {
+            let mut value = value as u64 & 0xFFFF_FFFF;
+
+            value = (value | (value << 16)) & 0x0000_FFFF_0000_FFFF;
+            value = (value | (value << 8)) & 0x00FF_00FF_00FF_00FF;
+            value = (value | (value << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+            value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+            (value | (value << 1)) & 0x5555_5555_5555_5555
+        };
+
+        spread(x) | (spread(y) << 1)
+    }
+
+    // Id of the particle closest to `pos`, None if there are no particles
+    pub fn get_closest_particle_id(&self, pos: &glm::Vec2, world_settings: &WorldSettings) -> Option<usize> {
+        self.query_nearest(pos, 1, world_settings).first().map(|&index| self.get_particle_id(index))
+    }
+
+    // The spatial queries return particle indices and measure across the edges of wrapping worlds like the forces do, 
+    // with the wrapping of the given settings. They use the partitions, which are kept up to date by `step`, `update_partitions` and every change to the particles, 
+    // but not by `update_particles` on its own
+
+    // Indices of the `k` particles closest to `center`, the closest one first
+    pub fn query_nearest(&self, center: &glm::Vec2, k: usize, world_settings: &WorldSettings) -> Vec<usize> {
+        if k == 0 || self.particle_positions.is_empty() {
+            return Vec::new();
+        }
+
+        // No distance across a wrapping world is longer than its diagonal
+        let max_radius = glm::length(&self.size);
+        let mut radius = self.cell_size.x.max(self.cell_size.y);
+
+        // Everything within the radius is found, so once there are enough particles in there the closest ones are among them
+        let mut found = loop {
+            let found = self.query_circle(center, radius, world_settings.wrapping);
+
+            if found.len() >= k || radius >= max_radius {
+                break found;
+            }
+
+            radius *= 2.0;
+        };
+
+        found.sort_unstable_by(|(a, a_distance_sq), (b, b_distance_sq)| a_distance_sq.total_cmp(b_distance_sq).then(a.cmp(b)));
+
+        found.into_iter().take(k).map(|(index, _)| index).collect()
+    }
+
+    // Indices of the particles within `radius` of `center`, in ascending order
+    pub fn query_radius(&self, center: &glm::Vec2, radius: f32, world_settings: &WorldSettings) -> Vec<usize> {
+        self.query_circle(center, radius, world_settings.wrapping).into_iter().map(|(index, _)| index).collect()
+    }
+
+    // Indices of the particles inside the rectangle, in ascending order. Parts of it outside a wrapping world continue on the other side
+    pub fn query_rect(&self, min: &glm::Vec2, max: &glm::Vec2, world_settings: &WorldSettings) -> Vec<usize> {
+        let mut found = Vec::new();
+
+        for (mirror, offset) in self.images(world_settings.wrapping) {
+            let (a, b) = (min.component_mul(&mirror) + offset, max.component_mul(&mirror) + offset);
+            let (image_min, image_max) = (glm::min2(&a, &b), glm::max2(&a, &b));
+
+            for partition in self.get_partitions_in_rect(&image_min, &image_max) {
+                found.extend(self.get_partition_particles(partition).iter().copied().filter(|&index| {
+                    let position = self.particle_positions[index];
+
+                    position.x >= image_min.x && position.x <= image_max.x && position.y >= image_min.y && position.y <= image_max.y
+                }));
+            }
+        }
+
+        found.sort_unstable();
+        found.dedup();
+
+        found
+    }
+
+    // Indices of the particles of the color, in ascending order
+    pub fn query_color(&self, color_id: u8) -> Vec<usize> {
+        (0..self.particle_color_ids.len()).filter(|&index| self.particle_color_ids[index] == color_id).collect()
+    }
+
+    // Indices and squared distances of the particles within `radius` of `center`, by index. 
+    // Particles close to several images of the center keep the shortest distance
+    fn query_circle(&self, center: &glm::Vec2, radius: f32, wrapping: ParticleWrapping) -> Vec<(usize, f32)> {
+        let radius_sq = radius * radius;
+        let extent = glm::Vec2::new(radius, radius);
+
+        let mut found = Vec::new();
+
+        for (mirror, offset) in self.images(wrapping) {
+            let image = center.component_mul(&mirror) + offset;
+
+            for partition in self.get_partitions_in_rect(&(image - extent), &(image + extent)) {
+                for &index in self.get_partition_particles(partition) {
+                    let distance_sq = glm::distance2(&self.particle_positions[index], &image);
+
+                    if distance_sq <= radius_sq {
+                        found.push((index, distance_sq));
+                    }
+                }
+            }
+        }
+
+        found.sort_unstable_by(|(a, a_distance_sq), (b, b_distance_sq)| a.cmp(b).then(a_distance_sq.total_cmp(b_distance_sq)));
+        found.dedup_by_key(|(index, _)| *index);
+
+        found
+    }
+
+    // Where a point appears when seen across each of the world's edges, as `point.component_mul(&mirror) + offset`. 
+    // Every particle's closest copy of a point is among these, the first one is the point itself
+    fn images(&self, wrapping: ParticleWrapping) -> Vec<(glm::Vec2, glm::Vec2)> {
+        let shifts = [0.0, -1.0, 1.0];
+
+        match wrapping {
+            ParticleWrapping::Wrap => shifts.iter().flat_map(|&j| shifts.iter().map(move |&i| {
+                (glm::Vec2::new(1.0, 1.0), glm::Vec2::new(i * self.size.x, j * self.size.y))
+            })).collect(),
+            // Crossing the top or bottom edge mirrors horizontally
+            ParticleWrapping::KleinBottle => shifts.iter().flat_map(|&j| shifts.iter().map(move |&i| {
+                (glm::Vec2::new(if j == 0.0 { 1.0 } else { -1.0 }, 1.0), glm::Vec2::new(i * self.size.x, j * self.size.y))
+            })).collect(),
+            // Crossing the left or right edge mirrors vertically, the top and bottom don't wrap
+            ParticleWrapping::MobiusStrip => shifts.iter().map(|&i| {
+                (glm::Vec2::new(1.0, if i == 0.0 { 1.0 } else { -1.0 }), glm::Vec2::new(i * self.size.x, 0.0))
+            }).collect(),
+            _ => vec![(glm::Vec2::new(1.0, 1.0), glm::Vec2::zeros())],
+        }
+    }
+
+    // Partitions overlapping the rectangle, the ones at the edges also hold the particles beyond them
+    fn get_partitions_in_rect(&self, min: &glm::Vec2, max: &glm::Vec2) -> Vec<usize> {
+        if self.partitions.is_empty() || max.x < -self.half_size.x || max.y < -self.half_size.y || min.x > self.half_size.x || min.y > self.half_size.y {
+            return Vec::new();
+        }
+
+        let cell = |value: f32, half_size: f32, cell_size: f32, cell_count: usize| {
+            (((value + half_size) / cell_size).floor().max(0.0) as usize).min(cell_count - 1)
+        };
+
+        let (min_x, max_x) = (cell(min.x, self.half_size.x, self.cell_size.x, self.cell_count_x), cell(max.x, self.half_size.x, self.cell_size.x, self.cell_count_x));
+        let (min_y, max_y) = (cell(min.y, self.half_size.y, self.cell_size.y, self.cell_count_y), cell(max.y, self.half_size.y, self.cell_size.y, self.cell_count_y));
+
+        (min_y..=max_y).flat_map(|y| (min_x..=max_x).map(move |x| y * self.cell_count_x + x)).collect()
+    }
+
+    fn get_partition_neighbors(&self, index: usize, wrapping: ParticleWrapping) -> Vec<PartitionNeighbor> {
+        // Itself, Left, Left Up, Left Down, Right, Right Up, Right Down, Up, Down
+        [(0, 0), (-1, 0), (-1, 1), (-1, -1), (1, 0), (1, 1), (1, -1), (0, 1), (0, -1)].into_iter().filter_map(|(d_x, d_y)| {
+            self.get_partition_neighbor(index, d_x, d_y, wrapping)
+        }).collect()
+    }
+
+    // The neighbors in one half of the directions, so every pair of neighboring partitions appears exactly once. 
+    // Crossing a mirrored edge flips the other axis, so the halves are split along the axis that never gets flipped
+    fn get_half_partition_neighbors(&self, index: usize, wrapping: ParticleWrapping) -> Vec<PartitionNeighbor> {
+        let directions = if wrapping == ParticleWrapping::MobiusStrip {
+            [(1, -1), (1, 0), (1, 1), (0, 1)]
+        } else {
+            [(-1, 1), (0, 1), (1, 1), (1, 0)]
+        };
+
+        directions.into_iter().filter_map(|(d_x, d_y)| {
+            self.get_partition_neighbor(index, d_x, d_y, wrapping)
+        }).collect()
+    }
+
+    // The partition `d_x`, `d_y` partitions away, None if that's outside of a world without wrapping
+    fn get_partition_neighbor(&self, index: usize, d_x: i32, d_y: i32, wrapping: ParticleWrapping) -> Option<PartitionNeighbor> {
+        let (w, h) = (self.cell_count_x as i32, self.cell_count_y as i32);
+
+        let x_i = (index % self.cell_count_x) as i32;
+        let y_i = (index / self.cell_count_x) as i32;
+
+        let (wrap_x, wrap_y) = match wrapping {
+            ParticleWrapping::Wrap | ParticleWrapping::KleinBottle => (true, true),
+            ParticleWrapping::MobiusStrip => (true, false),
+            _ => (false, false),
+        };
+
+        let (mut p_x, mut p_y) = (x_i + d_x, y_i + d_y);
+        let mut mirror = glm::Vec2::new(1.0, 1.0);
+        let mut offset = glm::Vec2::zeros();
+
+        // Crossing a mirrored edge flips the partition along the other axis, 
+        // which has to happen before that axis gets wrapped itself
+        if wrapping == ParticleWrapping::KleinBottle && (p_y < 0 || p_y >= h) {
+            p_x = w - 1 - p_x;
+            mirror.x = -1.0;
+        }
+        if wrapping == ParticleWrapping::MobiusStrip && (p_x < 0 || p_x >= w) {
+            p_y = h - 1 - p_y;
+            mirror.y = -1.0;
+        }
+
+        // Particles from the wrapped partitions are offset, so they appear right next to this one
+        if wrap_x {
+            if p_x < 0 {
+                p_x += w;
+                offset.x = -self.size.x * mirror.x;
+            } else if p_x >= w {
+                p_x -= w;
+                offset.x = self.size.x * mirror.x;
+            }
+        }
+        if wrap_y {
+            if p_y < 0 {
+                p_y += h;
+                offset.y = -self.size.y * mirror.y;
+            } else if p_y >= h {
+                p_y -= h;
+                offset.y = self.size.y * mirror.y;
+            }
+        }
+
+        if p_x < 0 || p_x >= w || p_y < 0 || p_y >= h {
+            return None;
+        }
+
+        Some(PartitionNeighbor {
+            partition: (p_y * w + p_x) as usize,
+            mirror,
+            offset
+        })
     }
 
     fn get_partition_id(&self, pos: &glm::Vec2) -> usize {
         let (x, y) = (
-            ((pos.x + self.half_size) / self.cell_size).floor() as usize, 
-            ((pos.y + self.half_size) / self.cell_size).floor() as usize
+            (((pos.x + self.half_size.x) / self.cell_size.x).floor() as usize).min(self.cell_count_x - 1), 
+            (((pos.y + self.half_size.y) / self.cell_size.y).floor() as usize).min(self.cell_count_y - 1)
         );
 
-        y * self.cell_count + x
+        y * self.cell_count_x + x
     }
 }
\ No newline at end of file
@@ -1,19 +1,220 @@
+use std::hash::{Hash, Hasher};
 use serde::{Serialize, Deserialize};
 use rand::{distributions::Alphanumeric, Rng};
+use crate::force_field::ForceField;
+use crate::spawn_pattern::SpawnPattern;
+use crate::stable_hasher::StableHasher;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ParticleWrapping {
+    // Clamps the position and keeps the velocity
     Barrier,
+    // Torus, both axes wrap around
     Wrap,
+    // Mirrors the position back inside and flips the velocity component
+    Reflect,
+    // Particles leaving the world respawn at a random position with no velocity
+    Absorb,
+    // Repulsive walls pushing the particles back over `wall_range`, clamped like `Barrier` as a last resort
+    SoftWall,
+    // Wraps around both axes, but crossing the top or bottom edge mirrors the particle horizontally
+    KleinBottle,
+    // Wraps around the left and right edges while mirroring the particle vertically, the top and bottom are barriers
+    MobiusStrip,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl ParticleWrapping {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParticleWrapping::Barrier => "Barrier",
+            ParticleWrapping::Wrap => "Wrap",
+            ParticleWrapping::Reflect => "Reflect",
+            ParticleWrapping::Absorb => "Absorb",
+            ParticleWrapping::SoftWall => "Soft Wall",
+            ParticleWrapping::KleinBottle => "Klein Bottle",
+            ParticleWrapping::MobiusStrip => "Möbius Strip",
+        }
+    }
+
+    pub fn all() -> [ParticleWrapping; 7] {
+        [
+            ParticleWrapping::Barrier,
+            ParticleWrapping::Wrap,
+            ParticleWrapping::Reflect,
+            ParticleWrapping::Absorb,
+            ParticleWrapping::SoftWall,
+            ParticleWrapping::KleinBottle,
+            ParticleWrapping::MobiusStrip,
+        ]
+    }
+}
+
+// Particles carry energy that changes with the color table's food and metabolism, 
+// they die once it runs out and divide when it reaches `division_energy`. New particles start with an energy of 1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EcosystemSettings {
+    pub enabled: bool,
+    pub division_energy: f32,
+    // No particles are born while there are this many
+    pub max_population: usize,
+}
+
+impl Default for EcosystemSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            division_energy: 2.0,
+            max_population: 20000,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Thermostat {
+    None,
+    // Scales the velocities towards the target temperature, closing the gap over about `coupling_time` seconds
+    Berendsen,
+    // Scales the velocities to exactly the target temperature every tick
+    VelocityRescaling,
+}
+
+impl Thermostat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Thermostat::None => "None",
+            Thermostat::Berendsen => "Berendsen",
+            Thermostat::VelocityRescaling => "Velocity Rescaling",
+        }
+    }
+
+    pub fn all() -> [Thermostat; 3] {
+        [Thermostat::None, Thermostat::Berendsen, Thermostat::VelocityRescaling]
+    }
+}
+
+// How the temperature changes from `temperature` to `final_temperature` over time
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Annealing {
+    // Stays at `temperature`
+    Constant,
+    // Reaches `final_temperature` after `annealing_time` and stays there
+    Linear,
+    // Approaches `final_temperature`, closing about 63% of the remaining gap every `annealing_time`
+    Exponential,
+    // Goes back and forth between both with a period of `annealing_time`
+    Cyclic,
+}
+
+impl Annealing {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Annealing::Constant => "Constant",
+            Annealing::Linear => "Linear",
+            Annealing::Exponential => "Exponential",
+            Annealing::Cyclic => "Cyclic",
+        }
+    }
+
+    pub fn all() -> [Annealing; 4] {
+        [Annealing::Constant, Annealing::Linear, Annealing::Exponential, Annealing::Cyclic]
+    }
+}
+
+// The temperature is the mean kinetic energy of the particles, `mass * speed^2 / 2`. 
+// Brownian noise kicks the particles randomly, balanced by the drag so free particles settle at the temperature 
+// (with very little drag they keep heating up instead), 
+// while a thermostat rescales the velocities to reach it regardless of the forces
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalSettings {
+    pub temperature: f32,
+    pub noise: bool,
+    pub thermostat: Thermostat,
+    // Only used by `Thermostat::Berendsen`
+    pub coupling_time: f32,
+
+    pub annealing: Annealing,
+    pub final_temperature: f32,
+    pub annealing_time: f32,
+}
+
+impl ThermalSettings {
+    // Target temperature `time` seconds after the particles were spawned
+    pub fn temperature_at(&self, time: f32) -> f32 {
+        let (from, to) = (self.temperature, self.final_temperature);
+        let t = time / self.annealing_time.max(f32::EPSILON);
+
+        match self.annealing {
+            Annealing::Constant => from,
+            Annealing::Linear => from + (to - from) * t.min(1.0),
+            Annealing::Exponential => to + (from - to) * (-t).exp(),
+            Annealing::Cyclic => to + (from - to) * (1.0 + (t * std::f32::consts::TAU).cos()) * 0.5,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.noise || self.thermostat != Thermostat::None
+    }
+}
+
+impl Default for ThermalSettings {
+    fn default() -> Self {
+        Self {
+            temperature: 1000.0,
+            noise: false,
+            thermostat: Thermostat::None,
+            coupling_time: 0.5,
+
+            annealing: Annealing::Constant,
+            final_temperature: 0.0,
+            annealing_time: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WorldSettings {
     pub max_particles: usize,
-    pub size: f32,
+    pub width: f32,
+    pub height: f32,
     pub wrapping: ParticleWrapping,
+    // Only used by `ParticleWrapping::SoftWall`, the wall acceleration grows linearly 
+    // from 0 at `wall_range` away from the edge to `wall_stiffness` at the edge
+    pub wall_stiffness: f32,
+    pub wall_range: f32,
+    // Lays the partitions out along a Morton curve instead of row by row, 
+    // which keeps more of the neighboring particles close in memory for large worlds
+    pub morton_order: bool,
     pub seed: String,
     pub bg_color: [f32; 3],
+
+    pub fixed_time_step: bool,
+    pub time_step: f32,
+    // Ticks per second of the simulation thread in the windowed app, 0 runs it as fast as possible
+    pub tick_rate: f32,
+    // Forces the fixed time step and derives every random choice from the seed,
+    // so the same seed, presets and tick count always produce the same particle state
+    pub deterministic: bool,
+    // Every tick is split into equal substeps no longer than `max_step`
+    pub max_step: f32,
+    // Shortens the substeps further, so the fastest particle moves at most `courant` times the smallest min_r per substep
+    pub adaptive_step: bool,
+    pub courant: f32,
+    // Upper bound on the substeps of a single tick, the rest of a very long tick is dropped instead
+    pub max_substeps: usize,
+    pub velocity_cap: bool,
+    pub max_speed: f32,
+
+    pub spawn_pattern: SpawnPattern,
+    // Relative amount of every color when spawning, empty for equal amounts
+    pub species_ratios: Vec<f32>,
+
+    pub ecosystem: EcosystemSettings,
+    pub thermal: ThermalSettings,
+
+    pub force_fields: Vec<ForceField>,
 }
 
 impl WorldSettings {
@@ -28,16 +229,59 @@ impl WorldSettings {
     pub fn new_random_seed(&mut self) {
         self.seed = Self::random_seed();
     }
+
+    pub fn seed_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.seed.hash(&mut hasher);
+        
+        hasher.finish()
+    }
+
+    // Independent seed for anything else than the particles themselves, e.g. the color table
+    pub fn sub_seed_hash(&self, name: &str) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.seed.hash(&mut hasher);
+        name.hash(&mut hasher);
+        
+        hasher.finish()
+    }
+
+    pub fn is_time_step_fixed(&self) -> bool {
+        self.fixed_time_step || self.deterministic
+    }
 }
 
 impl Default for WorldSettings {
     fn default() -> Self {
         Self { 
             max_particles: 10000,
-            size: 5000.0,
+            width: 5000.0,
+            height: 5000.0,
             wrapping: ParticleWrapping::Wrap,
+            wall_stiffness: 1000.0,
+            wall_range: 100.0,
+            morton_order: false,
             seed: Self::random_seed(),
-            bg_color: [0.0, 0.0, 0.0]
+            bg_color: [0.0, 0.0, 0.0],
+
+            fixed_time_step: false,
+            time_step: 0.016,
+            tick_rate: 60.0,
+            deterministic: false,
+            max_step: 0.02,
+            adaptive_step: false,
+            courant: 0.5,
+            max_substeps: 16,
+            velocity_cap: false,
+            max_speed: 2000.0,
+
+            spawn_pattern: SpawnPattern::Uniform,
+            species_ratios: Vec::new(),
+
+            ecosystem: EcosystemSettings::default(),
+            thermal: ThermalSettings::default(),
+
+            force_fields: Vec::new(),
         }
     }
 }
\ No newline at end of file
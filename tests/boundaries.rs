@@ -0,0 +1,101 @@
+mod common;
+
+use particle_life::{glm, ParticleSettings, ParticleWrapping};
+
+const TIME_STEP: f32 = 0.016;
+
+// Moves a single particle for one tick in a 1000x1000 world without drag, returns its new position and velocity
+fn cross(wrapping: ParticleWrapping, position: glm::Vec2, velocity: glm::Vec2) -> (glm::Vec2, glm::Vec2) {
+    let world_settings = common::world_settings(1000.0, 0, "boundaries", wrapping);
+    let particle_settings = ParticleSettings { drag: 1.0, ..Default::default() };
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    let index = world.add_particle(&position, 0);
+    world.set_particle_velocity(index, velocity);
+
+    world.step(TIME_STEP, &particle_settings, &world_settings, &color_table);
+
+    (world.get_particle_position(0), world.get_particle_velocities()[0])
+}
+
+fn assert_close(actual: glm::Vec2, expected: glm::Vec2) {
+    assert!(glm::distance(&actual, &expected) < 0.01, "expected {:?}, got {:?}", expected, actual);
+}
+
+#[test]
+fn barrier() {
+    let (position, velocity) = cross(ParticleWrapping::Barrier, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 0.0));
+
+    assert_close(position, glm::Vec2::new(499.9, 100.0));
+    assert_close(velocity, glm::Vec2::new(1000.0, 0.0));
+}
+
+#[test]
+fn wrap() {
+    let (position, velocity) = cross(ParticleWrapping::Wrap, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 0.0));
+
+    assert_close(position, glm::Vec2::new(-499.8, 100.0));
+    assert_close(velocity, glm::Vec2::new(1000.0, 0.0));
+}
+
+#[test]
+fn reflect() {
+    // 6.1 past the edge at 499.9, mirrored back inside with the velocity flipped
+    let (position, velocity) = cross(ParticleWrapping::Reflect, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 0.0));
+
+    assert_close(position, glm::Vec2::new(493.8, 100.0));
+    assert_close(velocity, glm::Vec2::new(-1000.0, 0.0));
+}
+
+#[test]
+fn absorb() {
+    let (position, velocity) = cross(ParticleWrapping::Absorb, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 0.0));
+
+    assert!(position.x.abs() <= 499.9 && position.y.abs() <= 499.9, "respawned outside the world at {:?}", position);
+    assert_close(velocity, glm::Vec2::zeros());
+}
+
+#[test]
+fn soft_wall() {
+    // 10 away from the edge, 90% of the way into the 100 wide wall, so it decelerates at 900 before getting clamped
+    let (position, velocity) = cross(ParticleWrapping::SoftWall, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 0.0));
+
+    assert_close(position, glm::Vec2::new(499.9, 100.0));
+    assert_close(velocity, glm::Vec2::new(1000.0 - 900.0 * TIME_STEP, 0.0));
+
+    let (position, velocity) = cross(ParticleWrapping::SoftWall, glm::Vec2::new(480.0, 100.0), glm::Vec2::zeros());
+
+    assert!(velocity.x < 0.0 && position.x < 480.0, "not pushed away from the wall: {:?} {:?}", position, velocity);
+}
+
+#[test]
+fn klein_bottle() {
+    // Crossing the top edge comes back in at the bottom, mirrored horizontally
+    let (position, velocity) = cross(ParticleWrapping::KleinBottle, glm::Vec2::new(100.0, 490.0), glm::Vec2::new(300.0, 1000.0));
+
+    assert_close(position, glm::Vec2::new(-104.8, -499.8));
+    assert_close(velocity, glm::Vec2::new(-300.0, 1000.0));
+
+    // The left and right edges wrap like a torus
+    let (position, velocity) = cross(ParticleWrapping::KleinBottle, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 300.0));
+
+    assert_close(position, glm::Vec2::new(-499.8, 104.8));
+    assert_close(velocity, glm::Vec2::new(1000.0, 300.0));
+}
+
+#[test]
+fn mobius_strip() {
+    // Crossing the right edge comes back in at the left, mirrored vertically
+    let (position, velocity) = cross(ParticleWrapping::MobiusStrip, glm::Vec2::new(490.0, 100.0), glm::Vec2::new(1000.0, 300.0));
+
+    assert_close(position, glm::Vec2::new(-499.8, -104.8));
+    assert_close(velocity, glm::Vec2::new(1000.0, -300.0));
+
+    // The top and bottom are barriers
+    let (position, velocity) = cross(ParticleWrapping::MobiusStrip, glm::Vec2::new(100.0, 490.0), glm::Vec2::new(0.0, 1000.0));
+
+    assert_close(position, glm::Vec2::new(100.0, 499.9));
+    assert_close(velocity, glm::Vec2::new(0.0, 1000.0));
+}
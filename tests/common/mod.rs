@@ -0,0 +1,29 @@
+// Shared by the integration tests and the benchmark, not every one of them uses everything in here
+#![allow(dead_code)]
+
+use particle_life::{World, WorldSettings, ParticleSettings, ColorTable, ParticleWrapping};
+
+// A square world with `count` particles, everything random is seeded by `seed`
+pub fn world_settings(size: f32, count: usize, seed: &str, wrapping: ParticleWrapping) -> WorldSettings {
+    WorldSettings {
+        max_particles: count,
+        width: size,
+        height: size,
+        wrapping,
+        seed: String::from(seed),
+        ..Default::default()
+    }
+}
+
+// The default colors with an attraction table seeded by the world settings
+pub fn color_table(world_settings: &WorldSettings) -> ColorTable {
+    ColorTable::new_seeded(&ColorTable::default_colors(), world_settings.sub_seed_hash("color_table"))
+}
+
+// A world with its particles spawned, empty when `max_particles` is 0
+pub fn world(world_settings: &WorldSettings, particle_settings: &ParticleSettings, color_table: &ColorTable) -> World {
+    let mut world = World::new(world_settings, particle_settings, color_table);
+    world.new_particles(world_settings, color_table);
+
+    world
+}
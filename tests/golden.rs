@@ -0,0 +1,33 @@
+mod common;
+
+use particle_life::{WorldSettings, ParticleSettings, ParticleWrapping};
+
+// Update only when a change is meant to alter the simulation, after checking the new behaviour
+const GOLDEN_HASH: u64 = 0xa235c6a2f02e80f0;
+
+fn run(threads: usize) -> u64 {
+    let world_settings = WorldSettings { deterministic: true, ..common::world_settings(1500.0, 500, "golden", ParticleWrapping::Wrap) };
+    let particle_settings = ParticleSettings::default();
+    let color_table = common::color_table(&world_settings);
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+
+    pool.install(|| {
+        let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+        for _ in 0..20 {
+            world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+        }
+
+        world.state_hash()
+    })
+}
+
+#[test]
+fn golden_state_hash() {
+    for threads in [1, 4] {
+        let hash = run(threads);
+
+        assert_eq!(hash, GOLDEN_HASH, "state hash with {} threads is {:016x}", threads, hash);
+    }
+}
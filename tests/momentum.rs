@@ -0,0 +1,30 @@
+mod common;
+
+use particle_life::{glm, ParticleSettings, ParticleWrapping};
+
+#[test]
+fn newtonian_mode_conserves_momentum() {
+    let world_settings = common::world_settings(1500.0, 400, "momentum", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings { newtonian: true, drag: 1.0, ..Default::default() };
+    let mut color_table = common::color_table(&world_settings);
+
+    // Unequal masses, so the reactions have to be divided by the right one
+    color_table.masses = (0..color_table.colors.len()).map(|color_id| 0.5 + color_id as f32).collect();
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    let initial = world.get_total_momentum(&color_table);
+
+    for _ in 0..50 {
+        world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+    }
+
+    // Compared to the momentum the particles would have if they all moved the same way
+    let scale: f32 = (0..world.get_particle_count()).map(|index| {
+        glm::length(&world.get_particle_velocities()[index]) * world.get_particle_mass(index, &color_table)
+    }).sum();
+    let drift = glm::distance(&world.get_total_momentum(&color_table), &initial);
+
+    assert!(scale > 0.0, "the particles never moved");
+    assert!(drift < scale * 1e-4, "momentum drifted by {} of {}", drift, scale);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use particle_life::{glm, World, ParticleSettings, ParticleWrapping};
+
+fn assert_indexed(world: &World) {
+    for index in 0..world.get_particle_count() {
+        assert_eq!(world.get_particle_index(world.get_particle_id(index)), Some(index));
+    }
+}
+
+#[test]
+fn ids_follow_the_particles() {
+    let world_settings = common::world_settings(1000.0, 300, "particle_ids", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings::default();
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    let followed = world.get_particle_id(123);
+    let position = world.get_particle_position(123);
+
+    // Reordered by partition
+    world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+    assert_indexed(&world);
+
+    let index = world.get_particle_index(followed).unwrap();
+    assert!(glm::distance(&world.get_particle_position(index), &position) < 100.0);
+
+    world.remove_particle(index);
+    assert_eq!(world.get_particle_index(followed), None);
+    assert_indexed(&world);
+
+    world.add_particles_in_circle(&glm::Vec2::zeros(), 100.0, 50, None, &color_table);
+    world.remove_particles_in_circle(&glm::Vec2::new(300.0, 300.0), 150.0, &world_settings);
+    world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+    assert_indexed(&world);
+    assert_eq!(world.get_particle_index(followed), None);
+}
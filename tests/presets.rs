@@ -0,0 +1,91 @@
+use std::io::ErrorKind;
+use particle_life::{saver, WorldSettings, ParticleSettings, ParticleWrapping, Integrator, Kernel, force_kernel::{Gaussian, Smoothstep}};
+
+// Saved before worlds could be rectangular, when `size` was used for both sides
+const BASELINE_WORLD: &str = r#"{"max_particles":8192,"size":3000.0,"wrapping":"Barrier","seed":"baseline","bg_color":[0.1,0.2,0.3]}"#;
+
+#[test]
+fn baseline_world_settings() {
+    let world_settings = saver::parse_world_settings(BASELINE_WORLD).unwrap();
+    let defaults = WorldSettings::default();
+
+    assert_eq!(world_settings.max_particles, 8192);
+    assert_eq!((world_settings.width, world_settings.height), (3000.0, 3000.0));
+    assert_eq!(world_settings.wrapping, ParticleWrapping::Barrier);
+    assert_eq!(world_settings.seed, "baseline");
+    assert_eq!(world_settings.bg_color, [0.1, 0.2, 0.3]);
+
+    // Everything added later falls back to the defaults
+    assert_eq!(world_settings.time_step, defaults.time_step);
+    assert_eq!(world_settings.max_substeps, defaults.max_substeps);
+    assert_eq!(world_settings.ecosystem, defaults.ecosystem);
+    assert_eq!(world_settings.thermal, defaults.thermal);
+    assert!(world_settings.force_fields.is_empty());
+    assert!(!world_settings.deterministic);
+}
+
+#[test]
+fn rectangular_world_settings_round_trip() {
+    let world_settings = WorldSettings { width: 4000.0, height: 1000.0, ..Default::default() };
+
+    let parsed = saver::parse_world_settings(&serde_json::to_string(&world_settings).unwrap()).unwrap();
+
+    assert_eq!(parsed, world_settings);
+}
+
+#[test]
+fn baseline_particle_settings() {
+    let particle_settings = saver::read_particle_settings("particles/uniform").unwrap();
+    let defaults = ParticleSettings::default();
+
+    assert_eq!((particle_settings.min_r, particle_settings.max_r), (399.9, 400.0));
+    assert_eq!(particle_settings.integrator, Integrator::SemiImplicitEuler);
+    assert_eq!(particle_settings.kernel, defaults.kernel);
+    assert_eq!(particle_settings.newtonian, defaults.newtonian);
+}
+
+#[test]
+fn baseline_color_table() {
+    let color_table = saver::read_color_table("palletes/Worms").unwrap();
+    let color_count = color_table.colors.len();
+
+    assert_eq!(color_count, 6);
+    assert_eq!(color_table.masses, vec![1.0; color_count]);
+    assert_eq!(color_table.min_r, vec![vec![None; color_count]; color_count]);
+    assert_eq!(color_table.max_r, vec![vec![None; color_count]; color_count]);
+    assert_eq!(color_table.food, vec![vec![0.0; color_count]; color_count]);
+    assert_eq!(color_table.metabolism.len(), color_count);
+    assert!(color_table.transmutations.is_empty());
+}
+
+#[test]
+fn color_tables_missing_entries() {
+    let colors = r#""colors":[[1.0,0.0,0.0],[0.0,1.0,0.0]]"#;
+
+    // Only has the entries baseline color tables had, the rest is filled in
+    let complete = format!(r#"{{{},"table":[[0.5,-0.5],[1.0,0.0]]}}"#, colors);
+    assert_eq!(saver::parse_color_table(&complete).unwrap().masses, vec![1.0, 1.0]);
+
+    let ragged_table = format!(r#"{{{},"table":[[0.5,-0.5],[1.0]]}}"#, colors);
+    let short_table = format!(r#"{{{},"table":[[0.5,-0.5]]}}"#, colors);
+    let short_max_r = format!(r#"{{{},"table":[[0.5,-0.5],[1.0,0.0]],"min_r":[[null,null],[null,null]],"max_r":[[null,null]]}}"#, colors);
+    let ragged_food = format!(r#"{{{},"table":[[0.5,-0.5],[1.0,0.0]],"food":[[0.0,0.0],[0.0]],"metabolism":[0.1,0.1]}}"#, colors);
+    let short_masses = format!(r#"{{{},"table":[[0.5,-0.5],[1.0,0.0]],"masses":[1.0]}}"#, colors);
+
+    for serialized in [ragged_table, short_table, short_max_r, ragged_food, short_masses] {
+        assert_eq!(saver::parse_color_table(&serialized).unwrap_err().kind(), ErrorKind::InvalidData, "{}", serialized);
+    }
+}
+
+#[test]
+fn kernel_parameters_out_of_range() {
+    let parse = |kernel: Kernel| saver::parse_particle_settings(&serde_json::to_string(&ParticleSettings { kernel, ..Default::default() }).unwrap());
+
+    for kernel in Kernel::all() {
+        assert!(parse(kernel).is_ok(), "{} kernel", kernel.name());
+    }
+
+    for kernel in [Kernel::Gaussian(Gaussian { width: 0.0 }), Kernel::Smoothstep(Smoothstep { peak: 1.0 }), Kernel::Smoothstep(Smoothstep { peak: 0.0 })] {
+        assert_eq!(parse(kernel).unwrap_err().kind(), ErrorKind::InvalidData, "{:?}", kernel);
+    }
+}
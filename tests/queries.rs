@@ -0,0 +1,98 @@
+mod common;
+
+use particle_life::{glm, World, WorldSettings, ParticleSettings, ParticleWrapping};
+
+// An empty 1000x1000 world with particles added at the given positions, their indices are the positions' indices
+fn world(wrapping: ParticleWrapping, positions: &[glm::Vec2]) -> (World, WorldSettings) {
+    let world_settings = common::world_settings(1000.0, 0, "queries", wrapping);
+    let particle_settings = ParticleSettings::default();
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    for position in positions {
+        world.add_particle(position, 0);
+    }
+
+    (world, world_settings)
+}
+
+#[test]
+fn radius_across_edges() {
+    let positions = [glm::Vec2::new(495.0, 0.0), glm::Vec2::new(-495.0, 0.0), glm::Vec2::new(0.0, 0.0)];
+
+    let (wrapped, wrapped_settings) = world(ParticleWrapping::Wrap, &positions);
+    let (barrier, barrier_settings) = world(ParticleWrapping::Barrier, &positions);
+
+    // 5 away from the left edge of the world, 10 from the first particle when measured across it
+    assert_eq!(wrapped.query_radius(&glm::Vec2::new(-495.0, 0.0), 20.0, &wrapped_settings), vec![0, 1]);
+    assert_eq!(barrier.query_radius(&glm::Vec2::new(-495.0, 0.0), 20.0, &barrier_settings), vec![1]);
+
+    // The settings are read on every query, so changing the wrapping doesn't need a tick
+    assert_eq!(wrapped.query_radius(&glm::Vec2::new(-495.0, 0.0), 20.0, &barrier_settings), vec![1]);
+}
+
+#[test]
+fn nearest_across_edges() {
+    let positions = [glm::Vec2::new(0.0, 490.0), glm::Vec2::new(0.0, -480.0), glm::Vec2::new(0.0, 400.0)];
+
+    let (wrapped, wrapped_settings) = world(ParticleWrapping::Wrap, &positions);
+    let (barrier, barrier_settings) = world(ParticleWrapping::Barrier, &positions);
+
+    // The second particle is 25 away across the top edge, closer than the third one at 95
+    assert_eq!(wrapped.query_nearest(&glm::Vec2::new(0.0, 495.0), 2, &wrapped_settings), vec![0, 1]);
+    assert_eq!(barrier.query_nearest(&glm::Vec2::new(0.0, 495.0), 2, &barrier_settings), vec![0, 2]);
+}
+
+#[test]
+fn rect_across_edges() {
+    let positions = [glm::Vec2::new(490.0, 0.0), glm::Vec2::new(-490.0, 0.0), glm::Vec2::new(-400.0, 0.0)];
+
+    let (wrapped, wrapped_settings) = world(ParticleWrapping::Wrap, &positions);
+    let (barrier, barrier_settings) = world(ParticleWrapping::Barrier, &positions);
+
+    // Reaches 20 past the right edge, which continues at the left one
+    let (min, max) = (glm::Vec2::new(480.0, -10.0), glm::Vec2::new(520.0, 10.0));
+
+    assert_eq!(wrapped.query_rect(&min, &max, &wrapped_settings), vec![0, 1]);
+    assert_eq!(barrier.query_rect(&min, &max, &barrier_settings), vec![0]);
+}
+
+#[test]
+fn mirrored_edges() {
+    // Crossing the top or bottom edge of a Klein bottle mirrors horizontally
+    let (klein_bottle, klein_bottle_settings) = world(ParticleWrapping::KleinBottle, &[glm::Vec2::new(100.0, 495.0)]);
+
+    assert_eq!(klein_bottle.query_radius(&glm::Vec2::new(-100.0, -495.0), 20.0, &klein_bottle_settings), vec![0]);
+    assert!(klein_bottle.query_radius(&glm::Vec2::new(100.0, -495.0), 20.0, &klein_bottle_settings).is_empty());
+
+    // Crossing the left or right edge of a Möbius strip mirrors vertically
+    let (mobius_strip, mobius_strip_settings) = world(ParticleWrapping::MobiusStrip, &[glm::Vec2::new(495.0, 100.0)]);
+
+    assert_eq!(mobius_strip.query_nearest(&glm::Vec2::new(-495.0, -100.0), 1, &mobius_strip_settings), vec![0]);
+    assert_eq!(mobius_strip.query_rect(&glm::Vec2::new(-510.0, -110.0), &glm::Vec2::new(-490.0, -90.0), &mobius_strip_settings), vec![0]);
+    assert!(mobius_strip.query_radius(&glm::Vec2::new(-495.0, 100.0), 20.0, &mobius_strip_settings).is_empty());
+}
+
+#[test]
+fn picked_particle_is_followed_by_id() {
+    let world_settings = common::world_settings(1000.0, 300, "queries", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings::default();
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    // Right after spawning the ids are the indices, the first step reorders the particles by partition
+    world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+
+    let position = world.get_particle_position(123);
+    let picked_id = world.get_closest_particle_id(&position, &world_settings).unwrap();
+
+    assert_eq!(picked_id, world.get_particle_id(123));
+    assert_ne!(picked_id, 123, "the particles weren't reordered");
+
+    world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+
+    let index = world.get_particle_index(picked_id).unwrap();
+    assert!(glm::distance(&world.get_particle_position(index), &position) < 100.0);
+}
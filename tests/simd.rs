@@ -0,0 +1,42 @@
+mod common;
+
+use particle_life::{glm, ParticleSettings, ParticleWrapping, Kernel, Integrator};
+
+// State hash and positions sorted by id after 10 ticks
+fn run(kernel: Kernel, integrator: Integrator, simd: bool) -> (u64, Vec<glm::Vec2>) {
+    let world_settings = common::world_settings(1000.0, 300, "simd", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings { kernel, integrator, simd, ..Default::default() };
+    let color_table = common::color_table(&world_settings);
+
+    let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+    for _ in 0..10 {
+        world.step(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+    }
+
+    let mut order: Vec<usize> = (0..world.get_particle_count()).collect();
+    order.sort_by_key(|&index| world.get_particle_id(index));
+
+    (world.state_hash(), order.iter().map(|&index| world.get_particle_position(index)).collect())
+}
+
+// Velocity Verlet also evaluates the forces while the particles aren't sorted by partition in memory, 
+// which takes the gathering path instead of loading the lanes directly
+#[test]
+fn simd_matches_scalar() {
+    for kernel in Kernel::all() {
+        for integrator in [Integrator::SemiImplicitEuler, Integrator::VelocityVerlet] {
+            let ((simd_hash, simd_positions), (scalar_hash, scalar_positions)) = (run(kernel, integrator, true), run(kernel, integrator, false));
+
+            match kernel {
+                // Their SIMD versions approximate the sine and the exponential
+                Kernel::Sine(_) | Kernel::Gaussian(_) => {
+                    let max_distance = simd_positions.iter().zip(&scalar_positions).map(|(a, b)| glm::distance(a, b)).fold(0.0, f32::max);
+
+                    assert!(max_distance < 0.001, "{} kernel with {:?} is {} off", kernel.name(), integrator, max_distance);
+                }
+                _ => assert_eq!(simd_hash, scalar_hash, "{} kernel with {:?}", kernel.name(), integrator),
+            }
+        }
+    }
+}
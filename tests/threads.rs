@@ -0,0 +1,41 @@
+mod common;
+
+use particle_life::{glm, ParticleSettings, ParticleWrapping};
+
+fn run(threads: usize) -> (Vec<glm::Vec2>, Vec<glm::Vec2>) {
+    let world_settings = common::world_settings(1500.0, 500, "threads", ParticleWrapping::Wrap);
+    let particle_settings = ParticleSettings::default();
+    let color_table = common::color_table(&world_settings);
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+
+    pool.install(|| {
+        let mut world = common::world(&world_settings, &particle_settings, &color_table);
+
+        for _ in 0..20 {
+            world.update_partitions();
+            world.update_particles(world_settings.time_step, &particle_settings, &world_settings, &color_table);
+        }
+
+        // Compared by id, so the memory layout doesn't matter
+        let mut order: Vec<usize> = (0..world.get_particle_count()).collect();
+        order.sort_by_key(|&index| world.get_particle_id(index));
+
+        (
+            order.iter().map(|&index| world.get_particle_positions()[index]).collect(),
+            order.iter().map(|&index| world.get_particle_velocities()[index]).collect()
+        )
+    })
+}
+
+#[test]
+fn multi_threaded_matches_single_threaded() {
+    let (reference_positions, reference_velocities) = run(1);
+
+    for threads in [2, 4, 8] {
+        let (positions, velocities) = run(threads);
+
+        assert_eq!(positions, reference_positions, "positions differ with {} threads", threads);
+        assert_eq!(velocities, reference_velocities, "velocities differ with {} threads", threads);
+    }
+}